@@ -0,0 +1,339 @@
+//! Aliases from the "RU_ALIAS" map, expanded wherever a command is run.
+//!
+//! An alias is a command with some args, and the args given after the alias name
+//! are added on the end, so with "ll = ls -l", "ll src" runs "ls -l src".
+//! Expanding the same alias twice in one chain is stopped, so "ls = ls -a" works.
+//!
+//! An alias that is more than one command, like "cd /tmp" or "make && make test",
+//! is run as statements, with the args added to the end of its text. These can only
+//! be used as a command of their own, not in a pipe or another alias.
+use crate::args::{Arg, Args};
+use crate::data::Data;
+use crate::exec::{Connection, Exec};
+use crate::expr::Expr;
+use crate::parser;
+use crate::statement::Statement;
+use crate::store::Store;
+use bogobble::traits::*;
+use err_tools::*;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+thread_local! {
+    /// The aliases currently running as statements, so they are not run again inside
+    static RUNNING: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The text of the alias for a command name
+pub fn get(name: &str, store: &Store) -> Option<String> {
+    let mut res = None;
+    store.do_with("RU_ALIAS", |d| {
+        if let Data::Map(m) = d {
+            res = m.get(name).map(|v| v.to_string());
+        }
+    });
+    res
+}
+
+fn parse(name: &str, text: &str) -> anyhow::Result<Vec<Statement>> {
+    parser::Lines
+        .parse_s(text)
+        .map_err(|e| SgError(format!("Alias '{}' is not a command : {}", name, e)).into())
+}
+
+/// The command, if the alias is a single command that can be part of a pipe
+fn as_exec(v: &[Statement]) -> Option<&Exec> {
+    match v {
+        [Statement::Expr(Expr::Exec(e))] => Some(e),
+        _ => None,
+    }
+}
+
+/// Runs ex as statements, if it names an alias that is more than one command.
+/// None if ex should be run as a program, with its aliases expanded
+pub fn run_statements(ex: &Exec, s: &mut Store) -> Option<anyhow::Result<bool>> {
+    if ex.conn.is_some() || RUNNING.with(|r| r.borrow().contains(&ex.command)) {
+        return None;
+    }
+    let text = get(&ex.command, s)?;
+    if as_exec(&parse(&ex.command, &text).ok()?).is_some() {
+        return None;
+    }
+    RUNNING.with(|r| r.borrow_mut().push(ex.command.clone()));
+    let res = run_with_args(&ex.command, text, &ex.args, s);
+    RUNNING.with(|r| r.borrow_mut().pop());
+    Some(res)
+}
+
+fn run_with_args(name: &str, mut text: String, args: &Args, s: &mut Store) -> anyhow::Result<bool> {
+    for a in args.run_s_vec(s, 0)? {
+        text.push(' ');
+        text.push_str(&quote(&a));
+    }
+    //Unlike a block, the result is the last statement's, so "tf && echo" sees false
+    let mut res = true;
+    for st in parse(name, &text)? {
+        crate::signals::check()?;
+        res = st.run(s)?;
+    }
+    Ok(res)
+}
+
+/// Expands aliases at the start of ex, and of anything it pipes to
+pub fn expand(ex: &Exec, store: &Store) -> anyhow::Result<Exec> {
+    expand_with(ex, store, &[])
+}
+
+fn expand_with(ex: &Exec, store: &Store, seen: &[&str]) -> anyhow::Result<Exec> {
+    let conn = match &ex.conn {
+        Some(c) => Some(Connection {
+            chan: c.chan.clone(),
+            target: Box::new(expand_with(&c.target, store, seen)?),
+        }),
+        None => None,
+    };
+    let text = match get(&ex.command, store) {
+        Some(t) if !seen.contains(&ex.command.as_str()) => t,
+        _ => {
+            return Ok(Exec {
+                command: ex.command.clone(),
+                args: ex.args.clone(),
+                conn,
+            })
+        }
+    };
+    let mut seen = seen.to_vec();
+    seen.push(&ex.command);
+    let v = parse(&ex.command, &text)?;
+    let first = as_exec(&v).e_string(format!(
+        "Alias '{}' is more than one command, so can only be run on its own",
+        ex.command
+    ))?;
+    let mut res = expand_with(first, store, &seen)?;
+
+    //The args and pipes given go after the last command of the alias
+    let mut tail = &mut res;
+    while tail.conn.is_some() {
+        tail = &mut tail.conn.as_mut().unwrap().target;
+    }
+    tail.args.0.extend(ex.args.0.iter().cloned());
+    tail.conn = conn;
+    Ok(res)
+}
+
+fn args_uses(a: &Args, store: &Store, res: &mut Vec<(String, String)>) {
+    for a in &a.0 {
+        arg_uses(a, store, res);
+    }
+}
+
+fn arg_uses(a: &Arg, store: &Store, res: &mut Vec<(String, String)>) {
+    match a {
+        Arg::Command(e) | Arg::ArrCommand(e) => exec_uses(e, store, res),
+        Arg::StringExpr(v) | Arg::HomeExpr(v) => v.iter().for_each(|a| arg_uses(a, store, res)),
+        Arg::List(l) => args_uses(l, store, res),
+        Arg::Map(m) => m.iter().for_each(|(_, a)| arg_uses(a, store, res)),
+        Arg::VarList(_, Some(a)) => arg_uses(a, store, res),
+        _ => {}
+    }
+}
+
+fn exec_uses(e: &Exec, store: &Store, res: &mut Vec<(String, String)>) {
+    if let Some(t) = get(&e.command, store) {
+        if !res.iter().any(|(k, _)| k == &e.command) {
+            res.push((e.command.clone(), t));
+        }
+    }
+    args_uses(&e.args, store, res);
+    if let Some(c) = &e.conn {
+        exec_uses(&c.target, store, res);
+    }
+}
+
+fn expr_uses(e: &Expr, store: &Store, res: &mut Vec<(String, String)>) {
+    match e {
+        Expr::Exec(ex) => exec_uses(ex, store, res),
+        Expr::Write { exec, filename, .. } => {
+            exec_uses(exec, store, res);
+            arg_uses(filename, store, res);
+        }
+        Expr::And(a, b) | Expr::Or(a, b) => {
+            expr_uses(a, store, res);
+            expr_uses(b, store, res);
+        }
+        Expr::Time(e) => expr_uses(e, store, res),
+    }
+}
+
+fn block_uses(b: &[Statement], store: &Store, res: &mut Vec<(String, String)>) {
+    for s in b {
+        match s {
+            Statement::Expr(e) => expr_uses(e, store, res),
+            Statement::Assign(_, _, a) | Statement::Builtin(_, a) => args_uses(a, store, res),
+            Statement::For { args, block, .. } => {
+                args_uses(args, store, res);
+                block_uses(block, store, res);
+            }
+            Statement::If { expr, block, else_ } => {
+                expr_uses(expr, store, res);
+                block_uses(block, store, res);
+                if let Some(b) = else_ {
+                    block_uses(b, store, res);
+                }
+            }
+            Statement::Disown(e) => exec_uses(e, store, res),
+        }
+    }
+}
+
+/// Each alias used by the line, with its text, for showing under the prompt
+pub fn uses(line: &str, store: &Store) -> Vec<(String, String)> {
+    let mut res = Vec::new();
+    if let Ok(v) = parser::Lines.parse_s(line) {
+        block_uses(&v, store, &mut res);
+    }
+    res
+}
+
+/// Quotes an arg that has already been evaluated, so it parses back as the same arg
+fn quote(a: &str) -> String {
+    let plain = |c: char| c.is_alphanumeric() || "-_./=:,+@%~".contains(c);
+    if !a.is_empty() && a.chars().all(plain) {
+        return a.to_string();
+    }
+    let mut h = String::new();
+    while a.contains(&format!("\"{}", h)) {
+        h.push('#');
+    }
+    format!("r{}\"{}\"{}", h, a, h)
+}
+
+/// The "alias" and "unalias" builtins.
+///  alias : lists them all
+///  alias name : shows one
+///  alias name cmd args.. : sets one, each arg kept as one arg
+///  alias name "cmd args | cmd" : sets one from the text of a command
+///  unalias name.. : removes them
+pub fn builtin(b: &str, args: &[String], store: &mut Store) -> anyhow::Result<bool> {
+    let mut all = match store.get("RU_ALIAS") {
+        Some(Data::Map(m)) => m,
+        _ => BTreeMap::new(),
+    };
+    match (b, args) {
+        ("alias", []) => {
+            for (k, v) in &all {
+                println!("alias {} {}", k, v);
+            }
+            return Ok(true);
+        }
+        ("alias", [name]) => {
+            let v = all.get(name).e_string(format!("No alias '{}'", name))?;
+            println!("alias {} {}", name, v);
+            return Ok(true);
+        }
+        //One arg is the whole command, as in alias pg "grep -i | less",
+        //otherwise each arg stays one arg
+        ("alias", [name, text]) => {
+            parse(name, text)?;
+            all.insert(name.clone(), Data::Str(text.clone()));
+        }
+        ("alias", [name, cmd, rest @ ..]) => {
+            let mut text = cmd.clone();
+            for a in rest {
+                text.push(' ');
+                text.push_str(&quote(a));
+            }
+            parse(name, &text)?;
+            all.insert(name.clone(), Data::Str(text));
+        }
+        (_, names) => {
+            for n in names {
+                all.remove(n).e_string(format!("No alias '{}'", n))?;
+            }
+        }
+    }
+    store.set("RU_ALIAS".to_string(), Data::Map(all));
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(line: &str, store: &mut Store) {
+        let v = parser::Lines.parse_s(line).unwrap();
+        crate::statement::run_block(&v, store).unwrap();
+    }
+
+    fn flat(e: &Exec, store: &mut Store) -> Vec<String> {
+        let mut res = vec![e.command.clone()];
+        res.extend(e.args.run_s_vec(store, 0).unwrap());
+        if let Some(c) = &e.conn {
+            res.push("|".to_string());
+            res.extend(flat(&c.target, store));
+        }
+        res
+    }
+
+    #[test]
+    fn expands_each_command() {
+        let mut store = Store::new();
+        run(
+            r#"alias ll ls -l
+alias ls ls -a
+alias loop1 loop2 x
+alias loop2 loop1 y
+alias pg "grep -i | less""#,
+            &mut store,
+        );
+        let ex = |s: &str, store: &mut Store| {
+            let e = parser::PExec.parse_s(s).unwrap();
+            let e = expand(&e, store).unwrap();
+            flat(&e, store).join(" ")
+        };
+        assert_eq!(ex("ll src | ll", &mut store), "ls -a -l src | ls -a -l");
+        assert_eq!(ex("loop1 z", &mut store), "loop1 y x z");
+        assert_eq!(ex("pg foo | wc", &mut store), "grep -i | less foo | wc");
+
+        let u = uses("echo $(ll) && pg x", &store);
+        let names: Vec<&str> = u.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(names, ["ll", "pg"]);
+
+        run(r###"alias g grep "a b" r#"$x "q""#"###, &mut store);
+        let e = expand(&parser::PExec.parse_s("g z").unwrap(), &store).unwrap();
+        assert_eq!(flat(&e, &mut store), ["grep", "a b", "$x \"q\"", "z"]);
+
+        run("unalias ll pg", &mut store);
+        assert!(get("ll", &store).is_none());
+        assert_eq!(get("ls", &store).as_deref(), Some("ls -a"));
+    }
+
+    #[test]
+    fn runs_statements() {
+        let mut store = Store::new();
+        run(
+            r#"let RU_ALIAS = {up = "cd /ru_no_such_dir"}
+alias two "echo a && echo b"
+alias tf "true && false"
+alias sets "let a = 1 ; let b = 2""#,
+            &mut store,
+        );
+        let line = |s: &str, store: &mut Store| {
+            let v = parser::Lines.parse_s(s).unwrap();
+            crate::statement::run_block(&v, store)
+        };
+        //cd is the builtin, not a program
+        let e = line("up", &mut store).unwrap_err().to_string();
+        assert!(!e.contains("not found"), "{}", e);
+
+        assert!(line("two", &mut store).is_ok());
+        let v = parser::Lines.parse_s("tf").unwrap();
+        match &v[0] {
+            Statement::Expr(e) => assert!(!e.run(&mut store).unwrap()),
+            _ => panic!("not an expr"),
+        }
+        line("sets", &mut store).unwrap();
+        assert_eq!(store.get("b").map(|d| d.to_string()).as_deref(), Some("2"));
+        assert!(line("two | cat", &mut store).is_err());
+    }
+}
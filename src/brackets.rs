@@ -0,0 +1,224 @@
+//! Finds which brackets and quotes pair up in a line, for the editor.
+//!
+//! This follows the same rules as the parsers, escapes, quotes, comments and raw
+//! strings, but it does not stop at errors, so it works on a line still being typed.
+//! Inside quotes only "$(", "$[" and "${" open brackets.
+use std::collections::BTreeMap;
+
+/// The char that closes an opening bracket or quote
+pub fn closer(c: char) -> Option<char> {
+    match c {
+        '(' => Some(')'),
+        '[' => Some(']'),
+        '{' => Some('}'),
+        '"' => Some('"'),
+        _ => None,
+    }
+}
+
+/// What the scan found up to a point in the line
+#[derive(Debug, Default)]
+pub struct Scan {
+    /// Each bracket that has a partner, mapped to that partner, both ways
+    pub pairs: BTreeMap<usize, usize>,
+    /// The brackets still open, innermost last
+    pub open: Vec<(char, usize)>,
+    /// Closers that did not match the innermost open bracket
+    pub stray: Vec<Stray>,
+    /// The scan stopped inside a raw string
+    pub raw: bool,
+}
+
+/// A closer found with nothing to close, or with open, of another kind, still open
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stray {
+    pub c: char,
+    pub pos: usize,
+    pub open: Option<(char, usize)>,
+}
+
+impl Scan {
+    pub fn in_quotes(&self) -> bool {
+        matches!(self.open.last(), Some(('"', _)))
+    }
+
+    /// How many brackets deep, for indenting
+    pub fn depth(&self) -> usize {
+        self.open.iter().filter(|(c, _)| *c != '"').count()
+    }
+
+    /// Whether c closes the innermost open bracket
+    pub fn closes(&self, c: char) -> bool {
+        match self.open.last() {
+            Some(&(o, _)) => closer(o) == Some(c),
+            None => false,
+        }
+    }
+
+    fn close(&mut self, c: char, pos: usize) {
+        match self.open.last() {
+            Some(&(o, start)) if closer(o) == Some(c) => {
+                self.open.pop();
+                self.pairs.insert(start, pos);
+                self.pairs.insert(pos, start);
+            }
+            open => self.stray.push(Stray {
+                c,
+                pos,
+                open: open.copied(),
+            }),
+        }
+    }
+}
+
+/// The length of a raw string like r#"..."# at the start of s, if it is one,
+/// and whether it is closed
+fn raw_len(s: &str) -> Option<(usize, bool)> {
+    let rest = s.strip_prefix('r')?;
+    let hashes = rest.len() - rest.trim_start_matches('#').len();
+    let body = rest[hashes..].strip_prefix('"')?;
+    let end = format!("\"{}", "#".repeat(hashes));
+    Some(match body.find(&end) {
+        Some(n) => (2 + hashes + n + end.len(), true),
+        None => (s.len(), false),
+    })
+}
+
+/// Scans s up to the byte index upto
+pub fn scan_to(s: &str, upto: usize) -> Scan {
+    let mut res = Scan::default();
+    let mut prev = ' ';
+    let mut it = s.char_indices().peekable();
+    while let Some((i, c)) = it.next() {
+        if i >= upto {
+            break;
+        }
+        let word_start = " \t\n;|&({[=".contains(prev);
+        prev = c;
+        match c {
+            '\\' => {
+                it.next();
+                prev = 'a';
+            }
+            '$' if res.in_quotes() => {
+                if let Some(&(n, o @ ('(' | '[' | '{'))) = it.peek() {
+                    if n < upto {
+                        res.open.push((o, n));
+                    }
+                    it.next();
+                }
+            }
+            '"' if res.in_quotes() => res.close('"', i),
+            _ if res.in_quotes() => {}
+            '"' => res.open.push(('"', i)),
+            '#' => {
+                while let Some(&(_, c)) = it.peek() {
+                    if c == '\n' || c == ';' {
+                        break;
+                    }
+                    it.next();
+                }
+            }
+            'r' if word_start => {
+                if let Some((n, closed)) = raw_len(&s[i..]) {
+                    //Still open where the scan stops, so it counts as a quote
+                    if i + n > upto || !closed {
+                        let q = i + s[i..].find('"').unwrap_or(0);
+                        if q < upto {
+                            res.open.push(('"', q));
+                            res.raw = true;
+                        }
+                        break;
+                    }
+                    while let Some(&(j, _)) = it.peek() {
+                        if j >= i + n {
+                            break;
+                        }
+                        it.next();
+                    }
+                    prev = 'a';
+                }
+            }
+            '(' | '[' | '{' => res.open.push((c, i)),
+            ')' | ']' | '}' => res.close(c, i),
+            _ => {}
+        }
+    }
+    res
+}
+
+pub fn scan(s: &str) -> Scan {
+    scan_to(s, s.len())
+}
+
+/// The partner of the bracket at pos, or failing that, of the one just before it
+pub fn near_match(s: &str, pos: usize) -> Option<usize> {
+    let sc = scan(s);
+    if let Some(n) = sc.pairs.get(&pos) {
+        return Some(*n);
+    }
+    let prev = s[..pos].char_indices().last()?.0;
+    sc.pairs.get(&prev).copied()
+}
+
+/// Sets the indent of each line after the first to unit times its depth,
+/// leaving lines that start inside quotes alone
+pub fn reindent(s: &str, unit: &str) -> String {
+    let mut res = String::new();
+    let mut start = 0;
+    for (n, l) in s.split('\n').enumerate() {
+        if n > 0 {
+            res.push('\n');
+            let sc = scan_to(s, start);
+            let body = l.trim_start();
+            if sc.in_quotes() {
+                res.push_str(l);
+            } else {
+                let d = match body.chars().next() {
+                    Some(c) if sc.closes(c) => sc.depth() - 1,
+                    _ => sc.depth(),
+                };
+                res.push_str(&unit.repeat(d));
+                res.push_str(body);
+            }
+        } else {
+            res.push_str(l);
+        }
+        start += l.len() + 1;
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn pairs_across_lines() {
+        let s = "for x in [a b] {\n  echo \"$(cat {}) \\\" ( \"\n}";
+        let sc = scan(s);
+        assert!(sc.open.is_empty());
+        assert_eq!(sc.pairs.get(&9), Some(&13));
+        assert_eq!(sc.pairs.get(&15), Some(&(s.len() - 1)));
+        //The quote pairs with the last quote, not the escaped one
+        assert_eq!(sc.pairs.get(&24), Some(&(s.len() - 3)));
+        //"$(" in quotes
+        assert_eq!(sc.pairs.get(&26), Some(&33));
+        assert_eq!(near_match(s, 14), Some(9));
+
+        let sc = scan("echo (a # ) not\n) r#\")\"# [");
+        assert_eq!(sc.pairs.get(&5), Some(&16));
+        assert_eq!(sc.open, [('[', 25)]);
+        assert!(scan_to("echo \"ab", 6).in_quotes());
+        assert!(scan_to("let a = r#\"x\"#", 12).in_quotes());
+        assert!(scan("let a = r\"x").in_quotes());
+    }
+
+    #[test]
+    fn indent_by_depth() {
+        let s = "for x in * {\nif $x {\necho \"a\n  b\"\n    }\n}";
+        assert_eq!(
+            reindent(s, "  "),
+            "for x in * {\n  if $x {\n    echo \"a\n  b\"\n  }\n}"
+        );
+    }
+}
@@ -0,0 +1,318 @@
+//! Completion specs from RU_COMPLETE.
+//!
+//! A spec is either a raw string of args, completed whatever the position,
+//! or a map with any of:
+//! * args : words to offer, a list or a raw string of args
+//! * sub : map of subcommand name to the spec used after it
+//! * flags : list of flags, or map of flag to what its value completes
+//! * files : a glob like "*.rs" to complete files, or "dir" for folders
+//! * gen : a command (or list of them) printing options, given the words so far on stdin,
+//!   killed if it takes longer than "RU_COMPLETE_TIMEOUT" ms (default 1000)
+//! * desc : map of word or flag to a description shown beside it
+//!
+//! Flag values, like files, may be "file", "dir", "none", a glob, or words.
+//! Flags of a command also apply after its subcommands.
+use crate::data::Data;
+use crate::menu::Candidate;
+use crate::parser;
+use crate::store::Store;
+use crate::tab_complete::{tab_complete_path, tab_complete_path_with};
+use bogobble::traits::*;
+use err_tools::*;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::mpsc;
+use std::time::Duration;
+
+fn get<'a>(spec: &'a Data, k: &str) -> Option<&'a Data> {
+    match spec {
+        Data::Map(m) => m.get(k),
+        _ => None,
+    }
+}
+
+/// Runs a list, or a string of args, into words
+fn words_of(d: &Data, store: &mut Store) -> anyhow::Result<Vec<String>> {
+    match d {
+        Data::List(l) => Ok(l.iter().map(|d| d.to_string()).collect()),
+        d => {
+            let args = parser::ArgsP
+                .parse_s(&d.to_string())
+                .map_err(|e| e.strung())?;
+            args.run_s_vec(store, 2)
+        }
+    }
+}
+
+/// Splits on unescaped whitespace
+pub fn split_words(s: &str) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut cur = String::new();
+    let mut esc = false;
+    for c in s.chars() {
+        match c {
+            _ if esc => {
+                cur.push(c);
+                esc = false;
+            }
+            '\\' => {
+                cur.push(c);
+                esc = true;
+            }
+            ' ' | '\t' | '\n' => {
+                if !cur.is_empty() {
+                    res.push(std::mem::take(&mut cur));
+                }
+            }
+            _ => cur.push(c),
+        }
+    }
+    if !cur.is_empty() {
+        res.push(cur);
+    }
+    res
+}
+
+/// What a flag does with the word after it, None if it takes no value.
+/// Checks the innermost spec first
+fn flag_value<'a>(chain: &[&'a Data], w: &str) -> Option<&'a Data> {
+    for spec in chain.iter().rev() {
+        if let Some(Data::Map(m)) = get(spec, "flags") {
+            if let Some(v) = m.get(w) {
+                return Some(v).filter(|v| v.to_string() != "none");
+            }
+        }
+    }
+    None
+}
+
+/// Completes cur from a value kind, such as a flag's value or "files"
+fn complete_kind(kind: &Data, cur: &str, store: &mut Store) -> anyhow::Result<Vec<Candidate>> {
+    let name_match = |p: &Path, pat: &glob::Pattern| {
+        p.is_dir()
+            || p.file_name()
+                .map(|f| pat.matches(&f.to_string_lossy()))
+                .unwrap_or(false)
+    };
+    let paths = match kind {
+        Data::List(_) => None,
+        k => match k.to_string().as_str() {
+            "file" => Some(tab_complete_path_with(cur, |_| true)),
+            "dir" => Some(tab_complete_path_with(cur, |p| p.is_dir())),
+            s if s.contains('*') => {
+                let pat = glob::Pattern::new(s)?;
+                Some(tab_complete_path_with(cur, |p| name_match(p, &pat)))
+            }
+            _ => None,
+        },
+    };
+    match paths {
+        Some(v) => Ok(v.into_iter().map(Candidate::from).collect()),
+        None => Ok(words_of(kind, store)?
+            .into_iter()
+            .map(Candidate::from)
+            .collect()),
+    }
+}
+
+fn gen_timeout(store: &Store) -> Duration {
+    let ms = store
+        .get("RU_COMPLETE_TIMEOUT")
+        .and_then(|d| d.to_string().parse().ok())
+        .unwrap_or(1000);
+    Duration::from_millis(ms)
+}
+
+/// Runs a generator command, writing the words so far to its input.
+/// Each line of output is an option, with an optional tab then description
+fn run_gen(
+    cmd: &str,
+    words: &[String],
+    cur: &str,
+    store: &Store,
+) -> anyhow::Result<Vec<Candidate>> {
+    let ex = parser::PExec.parse_s(cmd).map_err(|e| e.strung())?;
+    let mut ch_store = store.child();
+    let wlist = words.iter().map(|w| Data::Str(w.clone())).collect();
+    ch_store.let_set("RU_WORDS".to_string(), Data::List(wlist));
+    ch_store.let_set("RU_CURRENT".to_string(), Data::Str(cur.to_string()));
+    //Runs while editing, so a missing program must not run the user's hook
+    ch_store.let_set("RU_NOT_FOUND".to_string(), Data::Str(String::new()));
+    let mut ch = ex.run(&mut ch_store, Stdio::piped(), Stdio::piped(), Stdio::null())?;
+    if let Some(mut input) = ch.stdin.take() {
+        for w in words {
+            writeln!(input, "{}", w).ok();
+        }
+    }
+    let mut out = ch.stdout.take().e_str("No output from generator")?;
+    //Read aside, so a generator that hangs can't hold up the editor
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut buf = String::new();
+        tx.send(out.read_to_string(&mut buf).map(|_| buf)).ok();
+    });
+    let buf = match rx.recv_timeout(gen_timeout(store)) {
+        Ok(r) => r?,
+        Err(_) => {
+            ch.kill().ok();
+            ch.wait().ok();
+            return e_string(format!("Completion generator timed out : {}", cmd));
+        }
+    };
+    ch.wait().ok();
+    Ok(buf
+        .lines()
+        .filter(|l| !l.is_empty())
+        .map(|l| match l.split_once('\t') {
+            Some((w, d)) => Candidate::with_desc(w, d),
+            None => Candidate::new(l),
+        })
+        .collect())
+}
+
+/// Completes cur, given the words before it. words[0] is the command
+pub fn complete(
+    spec: &Data,
+    words: &[String],
+    cur: &str,
+    store: &mut Store,
+) -> anyhow::Result<Vec<Candidate>> {
+    let mut chain = vec![spec];
+    let mut i = 1;
+    while i < words.len() {
+        if let Some(kind) = flag_value(&chain, &words[i]) {
+            if i + 1 == words.len() {
+                return complete_kind(kind, cur, store);
+            }
+            i += 2;
+            continue;
+        }
+        let spec = chain[chain.len() - 1];
+        if let Some(sub) = get(spec, "sub").and_then(|s| get(s, &words[i])) {
+            chain.push(sub);
+        }
+        i += 1;
+    }
+    let spec = chain[chain.len() - 1];
+
+    let mut res = Vec::new();
+    if !matches!(spec, Data::Map(_)) {
+        //Flat list of args, kept from before specs, along with any path
+        for w in words_of(spec, store)? {
+            res.push(Candidate::new(w));
+        }
+        res.extend(tab_complete_path(cur).into_iter().map(Candidate::from));
+        return Ok(res);
+    }
+    if cur.starts_with('-') {
+        for sp in chain.iter().rev() {
+            match get(sp, "flags") {
+                Some(Data::Map(m)) => res.extend(m.keys().map(Candidate::new)),
+                Some(d) => res.extend(words_of(d, store)?.into_iter().map(Candidate::new)),
+                None => {}
+            }
+        }
+        return Ok(describe(res, &chain));
+    }
+    if let Some(Data::Map(m)) = get(spec, "sub") {
+        res.extend(m.keys().map(|k| Candidate::with_desc(k, "subcommand")));
+    }
+    if let Some(a) = get(spec, "args") {
+        res.extend(words_of(a, store)?.into_iter().map(Candidate::new));
+    }
+    match get(spec, "gen") {
+        Some(Data::List(l)) => {
+            for g in l {
+                res.extend(run_gen(&g.to_string(), words, cur, store)?);
+            }
+        }
+        Some(g) => res.extend(run_gen(&g.to_string(), words, cur, store)?),
+        None => {}
+    }
+    if let Some(f) = get(spec, "files") {
+        res.extend(complete_kind(f, cur, store)?);
+    }
+    Ok(describe(res, &chain))
+}
+
+/// Drops repeated words, and adds descriptions from the "desc" maps
+fn describe(v: Vec<Candidate>, chain: &[&Data]) -> Vec<Candidate> {
+    let mut res: Vec<Candidate> = Vec::new();
+    for mut c in v {
+        if res.iter().any(|r| r.text == c.text) {
+            continue;
+        }
+        for sp in chain.iter().rev() {
+            if let Some(d) = get(sp, "desc").and_then(|d| get(d, &c.text)) {
+                c.desc = Some(d.to_string());
+                break;
+            }
+        }
+        res.push(c);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn split_escaped() {
+        assert_eq!(
+            split_words("git  add my\\ file x"),
+            ["git", "add", "my\\ file", "x"]
+        );
+    }
+
+    #[test]
+    fn subcommands_and_flags() {
+        let mut store = Store::new();
+        let ar = parser::Lines
+            .parse_s(r#"let sp = {sub = {checkout = {args = [main dev]}}; flags = {"-C" = [a b]; "-v" = none}; args = [st]}"#)
+            .unwrap();
+        crate::statement::run_block(&ar, &mut store).unwrap();
+        let sp = store.get("sp").unwrap();
+        let w = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let texts = |v: Vec<Candidate>| v.into_iter().map(|c| c.text).collect::<Vec<_>>();
+
+        let r = complete(&sp, &w(&["git"]), "", &mut store).unwrap();
+        assert_eq!(texts(r), ["checkout", "st"]);
+        let r = complete(&sp, &w(&["git", "-v", "checkout"]), "", &mut store).unwrap();
+        assert_eq!(texts(r), ["main", "dev"]);
+        let r = complete(&sp, &w(&["git", "-C"]), "", &mut store).unwrap();
+        assert_eq!(texts(r), ["a", "b"]);
+        let r = complete(&sp, &w(&["git"]), "-", &mut store).unwrap();
+        assert_eq!(texts(r), ["-C", "-v"]);
+        let r = complete(&sp, &w(&["git", "checkout", "-C"]), "", &mut store).unwrap();
+        assert_eq!(texts(r), ["a", "b"]);
+    }
+
+    #[test]
+    fn slow_generators_time_out() {
+        let mut store = Store::new();
+        let ar = parser::Lines
+            .parse_s(r#"let RU_COMPLETE_TIMEOUT = 100; let sp = {gen = "sleep 5"}"#)
+            .unwrap();
+        crate::statement::run_block(&ar, &mut store).unwrap();
+        let sp = store.get("sp").unwrap();
+        let start = std::time::Instant::now();
+        let r = complete(&sp, &["x".to_string()], "", &mut store);
+        assert!(r.unwrap_err().to_string().contains("timed out"));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn missing_generator_skips_hook() {
+        let mut store = Store::new();
+        let mark = std::env::temp_dir().join(format!("ru_gen_hook_{}", std::process::id()));
+        let line = format!(
+            r#"let RU_NOT_FOUND = r"touch {}"; let sp = {{gen = "ru_no_such_gen"}}"#,
+            mark.display()
+        );
+        crate::statement::run_block(&parser::Lines.parse_s(&line).unwrap(), &mut store).unwrap();
+        let sp = store.get("sp").unwrap();
+        assert!(complete(&sp, &["x".to_string()], "", &mut store).is_err());
+        assert!(!mark.exists());
+    }
+}
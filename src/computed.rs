@@ -0,0 +1,305 @@
+//! Read only vars the shell works out itself, for prompts.
+//!
+//! They are worked out when asked for, and can't be set. The git ones are kept
+//! for the rest of the prompt, unless the directory changes.
+//! Git info is read straight from ".git", without running git.
+use crate::data::Data;
+use crate::prompt_cmd;
+use crate::timing::Times;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub const NAMES: &[&str] = &[
+    "RU_GIT_BRANCH",
+    "RU_GIT_DIRTY",
+    "RU_DURATION",
+    "RU_CPU",
+    "RU_STATUS",
+    "RU_TIME",
+    "RU_JOBS",
+    "RU_VENV",
+    "RU_NIX_SHELL",
+];
+
+static STATUS: AtomicI32 = AtomicI32::new(0);
+static TIMES: Mutex<Option<Times>> = Mutex::new(None);
+static JOBS: Mutex<Vec<Vec<u32>>> = Mutex::new(Vec::new());
+/// Values found so far, with the prompt and directory they were found for
+static CACHE: Mutex<BTreeMap<&str, (usize, PathBuf, Data)>> = Mutex::new(BTreeMap::new());
+
+pub fn is_computed(k: &str) -> bool {
+    NAMES.contains(&k)
+}
+
+/// Records how long the last command line took, for "RU_DURATION" and "RU_CPU"
+pub fn set_times(t: Times) {
+    *TIMES.lock().unwrap() = Some(t);
+}
+
+/// Records the exit status of the last program or statement, for "RU_STATUS"
+pub fn set_status(status: i32) {
+    STATUS.store(status, Ordering::SeqCst);
+}
+
+pub fn last_status() -> i32 {
+    STATUS.load(Ordering::SeqCst)
+}
+
+/// Records a disowned or stopped job, by the pids of its programs
+pub fn add_job(pids: &[u32]) {
+    let mut jobs = JOBS.lock().unwrap();
+    //A job stopped again after "fg" is already there, so moves to the end
+    jobs.retain(|j| !j.iter().any(|p| pids.contains(p)));
+    jobs.push(pids.to_vec());
+}
+
+/// The job added last that is still running or stopped
+pub fn last_job() -> Option<Vec<u32>> {
+    job_count();
+    JOBS.lock().unwrap().last().cloned()
+}
+
+/// Every program in the job pid is part of
+pub fn job_of(pid: u32) -> Vec<u32> {
+    job_count();
+    let jobs = JOBS.lock().unwrap();
+    let j = jobs.iter().find(|j| j.contains(&pid));
+    j.cloned().unwrap_or_else(|| vec![pid])
+}
+
+/// How many disowned or stopped jobs are still running, reaping programs that are done
+fn job_count() -> usize {
+    let mut jobs = JOBS.lock().unwrap();
+    for j in jobs.iter_mut() {
+        j.retain(|&pid| unsafe {
+            libc::waitpid(pid as libc::pid_t, std::ptr::null_mut(), libc::WNOHANG) == 0
+        });
+    }
+    jobs.retain(|j| !j.is_empty());
+    jobs.len()
+}
+
+/// A duration, shown as short as makes sense, eg "340ms", "2.1s", "3m05s"
+pub fn fmt_duration(d: Duration) -> String {
+    let s = d.as_secs();
+    match s {
+        0 => format!("{}ms", d.as_millis()),
+        1..=59 => format!("{:.1}s", d.as_secs_f64()),
+        60..=3599 => format!("{}m{:02}s", s / 60, s % 60),
+        _ => format!("{}h{:02}m", s / 3600, (s % 3600) / 60),
+    }
+}
+
+fn env(k: &str) -> String {
+    std::env::var(k).unwrap_or_default()
+}
+
+/// The ".git" dir for the repo dir is in, following a ".git" file to a worktree
+fn git_dir(dir: &Path) -> Option<PathBuf> {
+    for d in dir.ancestors() {
+        let g = d.join(".git");
+        if g.is_dir() {
+            return Some(g);
+        }
+        if let Ok(s) = std::fs::read_to_string(&g) {
+            let p = s.trim().strip_prefix("gitdir:")?.trim().to_string();
+            return Some(d.join(p));
+        }
+    }
+    None
+}
+
+/// The branch, or the short hash when HEAD is detached
+fn git_branch(git: &Path) -> Option<String> {
+    let head = std::fs::read_to_string(git.join("HEAD")).ok()?;
+    let head = head.trim();
+    match head.strip_prefix("ref:") {
+        Some(r) => {
+            let r = r.trim();
+            Some(r.strip_prefix("refs/heads/").unwrap_or(r).to_string())
+        }
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+fn be32(b: &[u8], at: usize) -> Option<u32> {
+    let v = b.get(at..at + 4)?;
+    Some(u32::from_be_bytes([v[0], v[1], v[2], v[3]]))
+}
+
+/// Whether any tracked file has changed since it was added to the index, judged by
+/// size and modified time, as git does before it looks closer.
+/// None if the index can't be read
+fn git_dirty(git: &Path, work: &Path) -> Option<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let b = std::fs::read(git.join("index")).ok()?;
+    if b.get(..4)? != b"DIRC" {
+        return None;
+    }
+    let version = be32(&b, 4)?;
+    let count = be32(&b, 8)?;
+    let mut at = 12;
+    let mut path: Vec<u8> = Vec::new();
+    for _ in 0..count {
+        let start = at;
+        let (mtime, mtime_ns) = (be32(&b, at + 8)?, be32(&b, at + 12)?);
+        let mode = be32(&b, at + 24)?;
+        let size = be32(&b, at + 36)?;
+        let flags = u16::from_be_bytes([*b.get(at + 60)?, *b.get(at + 61)?]);
+        at += 62;
+        let mut skip = flags & 0x8000 != 0;
+        if version >= 3 && flags & 0x4000 != 0 {
+            //skip-worktree
+            skip |= b.get(at)? & 0x40 != 0;
+            at += 2;
+        }
+        if version >= 4 {
+            //The path is the last one, less n bytes, and then the new part
+            let mut n = 0usize;
+            let mut c = *b.get(at)?;
+            at += 1;
+            n += (c & 0x7f) as usize;
+            while c & 0x80 != 0 {
+                c = *b.get(at)?;
+                at += 1;
+                n = ((n + 1) << 7) + (c & 0x7f) as usize;
+            }
+            path.truncate(path.len().checked_sub(n)?);
+            let end = at + b.get(at..)?.iter().position(|&c| c == 0)?;
+            path.extend_from_slice(&b[at..end]);
+            at = end + 1;
+        } else {
+            let end = at + b.get(at..)?.iter().position(|&c| c == 0)?;
+            path = b[at..end].to_vec();
+            at = start + ((end - start + 8) / 8) * 8;
+        }
+        //Submodules are their own repos
+        if skip || mode & 0o170000 == 0o160000 {
+            continue;
+        }
+        let md = match std::fs::symlink_metadata(work.join(String::from_utf8_lossy(&path).as_ref()))
+        {
+            Ok(md) => md,
+            Err(_) => return Some(true),
+        };
+        if md.size() as u32 != size
+            || md.mtime() as u32 != mtime
+            || (mtime_ns != 0 && md.mtime_nsec() as u32 != mtime_ns)
+        {
+            return Some(true);
+        }
+    }
+    Some(false)
+}
+
+fn work_dir(git: &Path) -> PathBuf {
+    match std::fs::read_to_string(git.join("commondir")) {
+        //A worktree says where it is in its "gitdir" file
+        Ok(_) => match std::fs::read_to_string(git.join("gitdir")) {
+            Ok(s) => Path::new(s.trim())
+                .parent()
+                .map(|p| p.to_path_buf())
+                .unwrap_or_default(),
+            Err(_) => PathBuf::new(),
+        },
+        Err(_) => git.parent().map(|p| p.to_path_buf()).unwrap_or_default(),
+    }
+}
+
+fn compute(k: &str, pwd: &Path) -> Data {
+    let s = match k {
+        "RU_GIT_BRANCH" => git_dir(pwd)
+            .and_then(|g| git_branch(&g))
+            .unwrap_or_default(),
+        "RU_GIT_DIRTY" => {
+            let dirty = git_dir(pwd).and_then(|g| git_dirty(&g, &work_dir(&g)));
+            return Data::Bool(dirty.unwrap_or(false));
+        }
+        "RU_DURATION" => match *TIMES.lock().unwrap() {
+            Some(t) => fmt_duration(t.wall),
+            None => String::new(),
+        },
+        "RU_CPU" => match *TIMES.lock().unwrap() {
+            Some(t) => fmt_duration(t.cpu()),
+            None => String::new(),
+        },
+        "RU_STATUS" => last_status().to_string(),
+        "RU_TIME" => chrono::Local::now().format("%H:%M:%S").to_string(),
+        "RU_JOBS" => job_count().to_string(),
+        "RU_VENV" => {
+            let v = env("VIRTUAL_ENV");
+            let name = Path::new(&v).file_name();
+            name.map(|n| n.to_string_lossy().to_string()).unwrap_or(v)
+        }
+        "RU_NIX_SHELL" => env("IN_NIX_SHELL"),
+        _ => String::new(),
+    };
+    Data::Str(s)
+}
+
+/// The value of a computed var, if k names one
+pub fn get(k: &str) -> Option<Data> {
+    let k = NAMES.iter().find(|n| **n == k)?;
+    let pwd = std::env::current_dir().unwrap_or_default();
+    //Only git is slow enough to keep
+    if !k.starts_with("RU_GIT") {
+        return Some(compute(k, &pwd));
+    }
+    let gen = prompt_cmd::generation();
+    let mut cache = CACHE.lock().unwrap();
+    if let Some((g, p, d)) = cache.get(k) {
+        if *g == gen && *p == pwd {
+            return Some(d.clone());
+        }
+    }
+    let d = compute(k, &pwd);
+    cache.insert(k, (gen, pwd, d.clone()));
+    Some(d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn git_from_dot_git() {
+        let dir = std::env::temp_dir().join(format!("ru_git_{}", std::process::id()));
+        let git = dir.join(".git");
+        std::fs::create_dir_all(git.join("refs/heads")).unwrap();
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(git.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        assert_eq!(git_dir(&dir.join("src")), Some(git.clone()));
+        assert_eq!(git_branch(&git), Some("main".to_string()));
+
+        //An index with one entry, for "a.txt"
+        std::fs::write(dir.join("a.txt"), "hello").unwrap();
+        let md = std::fs::metadata(dir.join("a.txt")).unwrap();
+        use std::os::unix::fs::MetadataExt;
+        let mut ix = b"DIRC".to_vec();
+        ix.extend(2u32.to_be_bytes());
+        ix.extend(1u32.to_be_bytes());
+        let entry_start = ix.len();
+        for v in [0, 0, md.mtime() as u32, md.mtime_nsec() as u32, 0, 0] {
+            ix.extend(v.to_be_bytes());
+        }
+        for v in [0o100644u32, 0, 0, 5] {
+            ix.extend(v.to_be_bytes());
+        }
+        ix.extend([0; 20]);
+        ix.extend(5u16.to_be_bytes());
+        ix.extend(b"a.txt");
+        while !(ix.len() - entry_start).is_multiple_of(8) || ix.last() != Some(&0) {
+            ix.push(0);
+        }
+        std::fs::write(git.join("index"), &ix).unwrap();
+        assert_eq!(git_dirty(&git, &work_dir(&git)), Some(false));
+        std::fs::write(dir.join("a.txt"), "hello world").unwrap();
+        assert_eq!(git_dirty(&git, &work_dir(&git)), Some(true));
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(fmt_duration(Duration::from_millis(2140)), "2.1s");
+        assert_eq!(fmt_duration(Duration::from_secs(185)), "3m05s");
+    }
+}
@@ -19,8 +19,9 @@ impl Cursor {
         res
     }
 
+    /// Puts the cursor at nv, or the start of the char nv is inside
     pub fn utf8_hit(&mut self, mut nv: usize) {
-        while self.s.get(nv..).is_some() && nv != 0 {
+        while self.s.get(nv..).is_none() && nv != 0 {
             nv -= 1;
         }
         self.index = nv;
@@ -274,3 +275,18 @@ impl PosFinder {
         v[l - 1].clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn hit_char_boundary() {
+        let mut c = Cursor::at_end("a你b".to_string());
+        c.utf8_hit(1);
+        assert_eq!(c.get_index(), 1);
+        c.utf8_hit(2);
+        assert_eq!(c.get_index(), 1);
+        c.utf8_hit(4);
+        assert_eq!(c.get_index(), 4);
+    }
+}
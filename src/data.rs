@@ -55,6 +55,7 @@ impl Data {
 
     pub fn is_true(&self) -> bool {
         match self {
+            Data::Bool(b) => *b,
             Data::Str(s) => match s.as_ref() {
                 "true" | "TRUE" | "1" => true,
                 _ => false,
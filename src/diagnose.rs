@@ -0,0 +1,229 @@
+//! Turns parse errors into messages saying what was expected, and where.
+//!
+//! Unclosed and stray brackets are found by the bracket scan, as the parser only
+//! knows which item it failed on. Anything else uses the tokens the parser expected.
+use crate::brackets::{self, Scan};
+use crate::parser;
+use crate::statement::Statement;
+use bogobble::traits::*;
+use bogobble::{Expected, StrungError};
+use err_tools::*;
+
+/// Names of parsers, as they should be put to the user
+const NAMES: &[(&str, &str)] = &[
+    ("End", "end of line"),
+    ("Ident", "a name"),
+    ("Path", "a command"),
+    ("PExec", "a command"),
+    ("ArgP", "an argument"),
+    ("Block", "`{`"),
+    ("Statement", "a statement"),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnosis {
+    /// Where in the text the problem is
+    pub index: usize,
+    pub msg: String,
+}
+
+impl Diagnosis {
+    /// The line and column of the problem, counting from 1
+    pub fn line_col(&self, s: &str) -> (usize, usize) {
+        let before = &s[..self.index.min(s.len())];
+        let line = before.matches('\n').count() + 1;
+        let col = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        (line, col)
+    }
+}
+
+fn line_of(s: &str, pos: usize) -> usize {
+    s[..pos].matches('\n').count() + 1
+}
+
+/// What an opener is called in messages
+fn describe(s: &str, c: char, pos: usize) -> String {
+    match (c, s[..pos].ends_with('$')) {
+        ('{', false) => "block".to_string(),
+        ('[', false) => "list".to_string(),
+        ('"', _) => "quote".to_string(),
+        (c, true) => format!("`${}`", c),
+        (c, false) => format!("`{}`", c),
+    }
+}
+
+/// What would close all of the open brackets
+fn closing(sc: &Scan) -> String {
+    let mut res = String::new();
+    for (c, _) in sc.open.iter().rev() {
+        match c {
+            '{' => res.push_str("\n}"),
+            c => res.extend(brackets::closer(*c)),
+        }
+    }
+    res
+}
+
+/// Whether s only fails to parse because brackets or quotes are still open
+fn only_unclosed(s: &str) -> bool {
+    let sc = brackets::scan(s);
+    if sc.open.is_empty() || !sc.stray.is_empty() {
+        return false;
+    }
+    let full = format!("{}{}", s, closing(&sc));
+    parser::Lines.parse_s(&full).is_ok()
+}
+
+/// Whether s failed with e because it is unfinished, so more lines could fix it,
+/// which is when the parser ran out of input, or only brackets are left open
+pub fn is_incomplete(s: &str, e: &StrungError) -> bool {
+    e.index.is_none() || only_unclosed(s)
+}
+
+fn tokens(e: &Expected, res: &mut Vec<String>) {
+    let t = match e {
+        Expected::OneOf(v) => {
+            for e in v {
+                tokens(e, res);
+            }
+            return;
+        }
+        Expected::Keyword(k) => return tokens(k, res),
+        Expected::EOI => "end of input".to_string(),
+        Expected::Char(c) if !c.is_whitespace() => format!("`{}`", c),
+        Expected::CharIn(s) if s.contains('\n') && s.contains(';') => "end of line".to_string(),
+        Expected::Str(s) => match NAMES.iter().find(|(k, _)| k == s) {
+            Some((_, v)) => v.to_string(),
+            None if s.starts_with(char::is_uppercase) || s.trim().is_empty() => return,
+            None => format!("`{}`", s.trim()),
+        },
+        _ => return,
+    };
+    if !res.contains(&t) {
+        res.push(t);
+    }
+}
+
+fn found(s: &str, index: usize) -> String {
+    let rest = s[index.min(s.len())..].trim_start_matches([' ', '\t']);
+    match rest.split(char::is_whitespace).next() {
+        Some(w) if !w.is_empty() => format!("`{}`", w),
+        _ if rest.starts_with('\n') => "end of line".to_string(),
+        _ => "end of input".to_string(),
+    }
+}
+
+/// Says why s failed to parse with e
+pub fn diagnose(s: &str, e: &StrungError) -> Diagnosis {
+    let sc = brackets::scan(s);
+    let stray = sc
+        .stray
+        .iter()
+        .find(|st| e.index.is_none_or(|i| i <= st.pos));
+    if let Some(st) = stray {
+        let msg = match st.open {
+            Some((o, pos)) => format!(
+                "expected `{}` to close {} opened on line {}, found `{}`",
+                brackets::closer(o).unwrap_or(o),
+                describe(s, o, pos),
+                line_of(s, pos),
+                st.c
+            ),
+            None => format!("unexpected `{}`, nothing is open to close", st.c),
+        };
+        return Diagnosis { index: st.pos, msg };
+    }
+    if let (Some(&(o, pos)), true) = (sc.open.last(), only_unclosed(s)) {
+        return Diagnosis {
+            index: s.len(),
+            msg: format!(
+                "expected `{}` to close {} opened on line {}",
+                brackets::closer(o).unwrap_or(o),
+                describe(s, o, pos),
+                line_of(s, pos)
+            ),
+        };
+    }
+    let index = e.index.unwrap_or(s.len());
+    let mut exp = Vec::new();
+    tokens(&e.exp, &mut exp);
+    let msg = match exp.as_slice() {
+        [] => format!("unexpected {}", found(s, index)),
+        [a] => format!("expected {}, found {}", a, found(s, index)),
+        [v @ .., last] => format!(
+            "expected {} or {}, found {}",
+            v.join(", "),
+            last,
+            found(s, index)
+        ),
+    };
+    Diagnosis { index, msg }
+}
+
+/// Parses a whole script, with errors given as "name:line:col: message"
+pub fn parse_lines(s: &str, name: &str) -> anyhow::Result<Vec<Statement>> {
+    parser::Lines.parse_s(s).map_err(|e| {
+        let d = diagnose(s, &e.strung());
+        let (l, c) = d.line_col(s);
+        SgError(format!("{}:{}:{}: {}", name, l, c, d.msg)).into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(s: &str) -> (String, (usize, usize)) {
+        let e = match parser::Lines.parse_s(s) {
+            Ok(_) => panic!("{} parsed", s),
+            Err(e) => e.strung(),
+        };
+        let d = diagnose(s, &e);
+        (d.msg.clone(), d.line_col(s))
+    }
+
+    #[test]
+    fn says_what_was_expected() {
+        assert_eq!(
+            msg("ls\nfor x in * {\necho hi\n"),
+            (
+                "expected `}` to close block opened on line 2".to_string(),
+                (4, 1)
+            )
+        );
+        assert_eq!(
+            msg("echo \"a $(ls"),
+            (
+                "expected `)` to close `$(` opened on line 1".to_string(),
+                (1, 13)
+            )
+        );
+        assert_eq!(
+            msg("echo a }"),
+            (
+                "unexpected `}`, nothing is open to close".to_string(),
+                (1, 8)
+            )
+        );
+        assert_eq!(
+            msg("if true {\n  echo a\n} els {\n}"),
+            (
+                "expected end of line or `else`, found `els`".to_string(),
+                (3, 3)
+            )
+        );
+        assert_eq!(
+            msg("let = 3"),
+            ("expected a name, found `=`".to_string(), (1, 5))
+        );
+        let inc = |s: &str| match parser::Lines.parse_s(s) {
+            Ok(_) => false,
+            Err(e) => is_incomplete(s, &e.strung()),
+        };
+        assert!(inc("echo \"abc"));
+        assert!(inc("echo a &&"));
+        assert!(inc("for x in [a b] {\necho $(ls"));
+        assert!(!inc("echo a }"));
+        assert!(!inc("if true {\n} els {\n}"));
+    }
+}
@@ -0,0 +1,85 @@
+//! One channel for everything the interactive loop waits on.
+//!
+//! Keys are read on their own thread, but only one event at a time, when the loop
+//! asks for it, so nothing is read while a command has the terminal. Other threads
+//! can send "Redraw" to have the prompt drawn again, and "Resize" when the terminal
+//! changes size.
+use std::io::{self, stdin};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+use termion::event::Event;
+use termion::input::TermReadEventsAndRaw;
+
+pub enum Msg {
+    Input(io::Result<Event>),
+    Redraw,
+    Resize,
+}
+
+static WAKE: Mutex<Option<Sender<Msg>>> = Mutex::new(None);
+
+fn send(m: Msg) {
+    if let Some(tx) = WAKE.lock().unwrap().as_ref() {
+        tx.send(m).ok();
+    }
+}
+
+/// Asks the loop to draw the prompt again, if there is a loop
+pub fn redraw() {
+    send(Msg::Redraw)
+}
+
+pub fn resize() {
+    send(Msg::Resize)
+}
+
+pub struct Events {
+    rx: Receiver<Msg>,
+    want: Sender<()>,
+    /// The reader has been asked for an event it has not sent yet
+    asked: bool,
+}
+
+impl Events {
+    pub fn start() -> Self {
+        let (tx, rx) = channel();
+        let (want, wanted) = channel::<()>();
+        *WAKE.lock().unwrap() = Some(tx.clone());
+        std::thread::spawn(move || {
+            let mut it = stdin().events_and_raw();
+            while wanted.recv().is_ok() {
+                let m = match it.next() {
+                    Some(r) => Msg::Input(r.map(|(e, _)| e)),
+                    //Dropping the last senders ends the loop
+                    None => {
+                        WAKE.lock().unwrap().take();
+                        return;
+                    }
+                };
+                if tx.send(m).is_err() {
+                    return;
+                }
+            }
+        });
+        Events {
+            rx,
+            want,
+            asked: false,
+        }
+    }
+}
+
+impl Iterator for Events {
+    type Item = Msg;
+    fn next(&mut self) -> Option<Msg> {
+        if !self.asked {
+            self.want.send(()).ok()?;
+            self.asked = true;
+        }
+        let m = self.rx.recv().ok()?;
+        if let Msg::Input(_) = m {
+            self.asked = false;
+        }
+        Some(m)
+    }
+}
@@ -1,5 +1,8 @@
+use crate::alias;
 use crate::args::Args;
 use crate::channel::*;
+use crate::job::Job;
+use crate::not_found;
 use crate::store::Store;
 use err_tools::*;
 use std::process::{Child, Command, Stdio};
@@ -17,12 +20,14 @@ impl Connection {
         sets: &mut Store,
         out: Stdio,
         err: Stdio,
+        job: &mut Job,
     ) -> anyhow::Result<Child> {
         let iread = self
             .chan
             .as_reader(ch.stdout.e_str("No output")?, ch.stderr.e_str("No errput")?);
 
-        self.target.run(sets, iread.to_stdio(), out, err)
+        self.target
+            .run_expanded(sets, iread.to_stdio(), out, err, job)
     }
 }
 
@@ -34,31 +39,62 @@ pub struct Exec {
 }
 
 impl Exec {
+    /// Starts the program, leaving any connection to the caller
+    fn spawn(
+        &self,
+        s: &mut Store,
+        input: Stdio,
+        output: Stdio,
+        errput: Stdio,
+        job: &mut Job,
+    ) -> anyhow::Result<Child> {
+        let args = self.args.run_s_vec(s, 3)?;
+        let mut cmd = Command::new(&self.command);
+        cmd.args(&args).stdin(input).stdout(output).stderr(errput);
+        job.setup(&mut cmd);
+        let ch = cmd
+            .spawn()
+            .map_err(|e| not_found::spawn_error(e, &self.command, &args, s))?;
+        job.started(ch.id());
+        Ok(ch)
+    }
+
     pub fn run(
         &self,
         s: &mut Store,
         input: Stdio,
         output: Stdio,
         errput: Stdio,
+    ) -> anyhow::Result<Child> {
+        self.run_job(s, input, output, errput, &mut Job::none())
+    }
+
+    /// Runs with the programs started put in job
+    pub fn run_job(
+        &self,
+        s: &mut Store,
+        input: Stdio,
+        output: Stdio,
+        errput: Stdio,
+        job: &mut Job,
+    ) -> anyhow::Result<Child> {
+        alias::expand(self, s)?.run_expanded(s, input, output, errput, job)
+    }
+
+    /// Runs without looking for aliases, as expand has already been through the chain
+    fn run_expanded(
+        &self,
+        s: &mut Store,
+        input: Stdio,
+        output: Stdio,
+        errput: Stdio,
+        job: &mut Job,
     ) -> anyhow::Result<Child> {
         match &self.conn {
-            None => Command::new(&self.command)
-                .args(self.args.run_s_vec(s, 3)?)
-                .stdin(input)
-                .stdout(output)
-                .stderr(errput)
-                .spawn()
-                .e_string(format!("Error running {}", &self.command)),
-            //.map_err(Into::into),
+            None => self.spawn(s, input, output, errput, job),
             Some(conn) => {
-                let ch = Command::new(&self.command)
-                    .args(self.args.run_s_vec(s, 3)?)
-                    .stdin(input)
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::piped())
-                    .spawn()
-                    .e_string(format!("Error running {}", &self.command))?;
-                conn.run(ch, s, output, errput)
+                let ch = self.spawn(s, input, Stdio::piped(), Stdio::piped(), job)?;
+                conn.run(ch, s, output, errput, job)
             }
         }
     }
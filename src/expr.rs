@@ -1,9 +1,15 @@
+use crate::alias;
 use crate::args::*;
 use crate::channel::*;
+use crate::computed;
 use crate::exec::*;
+use crate::job::Job;
+use crate::signals;
 use crate::store::Store;
+use crate::timing::{self, Timer};
 use err_tools::*;
-use std::process::Stdio;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{ExitStatus, Stdio};
 
 #[derive(Debug)]
 pub enum Expr {
@@ -16,14 +22,27 @@ pub enum Expr {
     },
     And(Box<Expr>, Box<Expr>),
     Or(Box<Expr>, Box<Expr>),
+    Time(Box<Expr>),
 }
 
 impl Expr {
     pub fn run(&self, s: &mut Store) -> anyhow::Result<bool> {
         match self {
             Expr::Exec(e) => {
-                let mut ch = e.run(s, Stdio::inherit(), Stdio::inherit(), Stdio::inherit())?;
-                ch.wait().map(|e| e.success()).map_err(Into::into)
+                if let Some(res) = alias::run_statements(e, s) {
+                    return res;
+                }
+                let mut job = Job::foreground();
+                let res = e.run_job(
+                    s,
+                    Stdio::inherit(),
+                    Stdio::inherit(),
+                    Stdio::inherit(),
+                    &mut job,
+                );
+                let st = res.and_then(|mut ch| Ok(timing::wait_job(&job, &mut ch)?));
+                job.done();
+                ran(st?)
             }
             Expr::Write {
                 exec,
@@ -32,19 +51,37 @@ impl Expr {
                 append,
             } => {
                 let filename = filename.run(s, 0)?.to_string();
-                let ch = exec.run(s, Stdio::inherit(), Stdio::piped(), Stdio::piped())?;
-                let mut iread =
-                    chan.as_reader(ch.stdout.e_str("No Output")?, ch.stderr.e_str("No ErrPut")?);
-
                 let mut f = std::fs::OpenOptions::new()
                     .append(*append)
                     .truncate(!*append)
                     .write(true)
                     .create(true)
                     .open(filename)?;
-                std::io::copy(&mut iread, &mut f)
-                    .map(|_| true)
-                    .e_str("Could not copy to output file")
+                let mut job = Job::foreground();
+                let res = exec.run_job(
+                    s,
+                    Stdio::inherit(),
+                    Stdio::piped(),
+                    Stdio::piped(),
+                    &mut job,
+                );
+                let st = res.and_then(|mut ch| {
+                    let mut iread = chan.as_reader(
+                        ch.stdout.take().e_str("No Output")?,
+                        ch.stderr.take().e_str("No ErrPut")?,
+                    );
+                    //Copied on its own thread, so the shell sees Ctrl-Z stop the job.
+                    //A stopped job's output is still copied once "fg" carries it on
+                    let copy = std::thread::spawn(move || std::io::copy(&mut iread, &mut f));
+                    let st = timing::wait_job(&job, &mut ch)?;
+                    if st.stopped_signal().is_none() {
+                        let copied = copy.join().ok().e_str("Could not copy to output file")?;
+                        copied.e_str("Could not copy to output file")?;
+                    }
+                    Ok(st)
+                });
+                job.done();
+                ran(st?)
             }
             Expr::And(a, b) => match a.run(s) {
                 Ok(true) => b.run(s),
@@ -54,6 +91,31 @@ impl Expr {
                 Ok(false) => b.run(s),
                 v => v,
             },
+            Expr::Time(e) => {
+                let t = Timer::start();
+                let res = e.run(s);
+                eprintln!("time : {}", t.stop());
+                res
+            }
         }
     }
 }
+
+/// Records the status of a foreground job, true if it succeeded
+fn ran(st: ExitStatus) -> anyhow::Result<bool> {
+    //The shell may not have heard of Ctrl-C yet, but the program has
+    if st.signal() == Some(libc::SIGINT) {
+        signals::interrupt();
+    }
+    computed::set_status(exit_code(&st));
+    Ok(st.success())
+}
+
+/// The exit code, or 128 + the signal that killed or stopped it, as other shells give
+pub fn exit_code(st: &ExitStatus) -> i32 {
+    match (st.code(), st.signal().or(st.stopped_signal())) {
+        (Some(c), _) => c,
+        (None, Some(sig)) => 128 + sig,
+        _ => 1,
+    }
+}
@@ -0,0 +1,431 @@
+//! Imports completions written for fish, so existing completion files can be reused.
+//!
+//! The "complete -c" lines of <dir>/<command>.fish become a spec as in comp_spec.
+//! The dirs searched are in RU_FISH_COMPLETE, a list or a ':' separated string.
+//! Conditions other than "__fish_use_subcommand" and "__fish_seen_subcommand_from"
+//! need fish itself to check, so those lines are skipped.
+//! Commands in "(...)" args would be run by this shell, not fish, so they are only
+//! kept if their program is in RU_FISH_GEN, otherwise only the plain words are used.
+use crate::data::Data;
+use crate::parser;
+use crate::store::Store;
+use bogobble::traits::*;
+use bogobble::EOI;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::Chars;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+const DEFAULT_DIRS: &[&str] = &[
+    "~/.config/fish/completions",
+    "/usr/share/fish/vendor_completions.d",
+    "/usr/share/fish/completions",
+];
+
+/// Specs already read, kept until their file or RU_FISH_GEN changes
+type Cached = (SystemTime, Vec<String>, Data);
+static CACHE: Mutex<BTreeMap<PathBuf, Cached>> = Mutex::new(BTreeMap::new());
+
+fn expand_home(s: &str) -> PathBuf {
+    match s.strip_prefix("~/") {
+        Some(rest) => PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(rest),
+        None => PathBuf::from(s),
+    }
+}
+
+pub fn dirs(store: &Store) -> Vec<PathBuf> {
+    match store.get("RU_FISH_COMPLETE") {
+        Some(Data::List(l)) => l.iter().map(|d| expand_home(&d.to_string())).collect(),
+        Some(d) => d
+            .to_string()
+            .split(':')
+            .filter(|s| !s.is_empty())
+            .map(expand_home)
+            .collect(),
+        None => DEFAULT_DIRS.iter().map(|s| expand_home(s)).collect(),
+    }
+}
+
+/// The programs fish files may run to generate options, from RU_FISH_GEN
+pub fn allowed_gens(store: &Store) -> Vec<String> {
+    match store.get("RU_FISH_GEN") {
+        Some(Data::List(l)) => l.iter().map(|d| d.to_string()).collect(),
+        Some(d) => d.to_string().split_whitespace().map(String::from).collect(),
+        None => Vec::new(),
+    }
+}
+
+/// The spec from the first fish file found for cmd
+pub fn spec_for(cmd: &str, store: &Store) -> Option<Data> {
+    if cmd.is_empty() || cmd.contains('/') {
+        return None;
+    }
+    let allow = allowed_gens(store);
+    for d in dirs(store) {
+        let p = d.join(format!("{}.fish", cmd));
+        let mtime = match std::fs::metadata(&p).and_then(|m| m.modified()) {
+            Ok(t) => t,
+            Err(_) => continue,
+        };
+        let mut cache = CACHE.lock().ok()?;
+        if let Some((t, a, sp)) = cache.get(&p) {
+            if *t == mtime && *a == allow {
+                return Some(sp.clone());
+            }
+        }
+        let s = match std::fs::read_to_string(&p) {
+            Ok(s) => s,
+            Err(_) => continue,
+        };
+        let sp = parse_spec(&s, cmd, &allow);
+        cache.insert(p, (mtime, allow, sp.clone()));
+        return Some(sp);
+    }
+    None
+}
+
+/// Reads up to the closing quote, fish only allows escaping the quote and '\\'
+fn quoted(it: &mut Chars, q: char, w: &mut String) {
+    while let Some(c) = it.next() {
+        match c {
+            '\\' => match it.next() {
+                Some(e) if e == q || e == '\\' || (q == '"' && e == '$') => w.push(e),
+                Some(e) => {
+                    w.push('\\');
+                    w.push(e);
+                }
+                None => {}
+            },
+            c if c == q => return,
+            c => w.push(c),
+        }
+    }
+}
+
+/// Splits a line into words the way fish does, without expanding anything
+fn tokens(line: &str) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut cur: Option<String> = None;
+    let mut it = line.chars();
+    while let Some(c) = it.next() {
+        match c {
+            '#' if cur.is_none() => break,
+            ' ' | '\t' => res.extend(cur.take()),
+            '\'' | '"' => quoted(&mut it, c, cur.get_or_insert_with(String::new)),
+            '\\' => cur.get_or_insert_with(String::new).extend(it.next()),
+            c => cur.get_or_insert_with(String::new).push(c),
+        }
+    }
+    res.extend(cur);
+    res
+}
+
+/// Joins lines ending in '\' with the line after
+fn logical_lines(s: &str) -> Vec<String> {
+    let mut res = Vec::new();
+    let mut cur = String::new();
+    for l in s.lines() {
+        match l.strip_suffix('\\') {
+            Some(l) => {
+                cur.push_str(l);
+                cur.push(' ');
+            }
+            None => {
+                cur.push_str(l);
+                res.push(std::mem::take(&mut cur));
+            }
+        }
+    }
+    if !cur.is_empty() {
+        res.push(cur);
+    }
+    res
+}
+
+/// One "complete" line
+#[derive(Debug, Default)]
+struct Entry {
+    cmd: Option<String>,
+    flags: Vec<String>,
+    args: Option<String>,
+    desc: Option<String>,
+    cond: Option<String>,
+    no_files: bool,
+    force_files: bool,
+    require: bool,
+}
+
+fn entry(toks: &[String]) -> Option<Entry> {
+    if toks.first()? != "complete" {
+        return None;
+    }
+    let mut e = Entry::default();
+    let mut it = toks[1..].iter();
+    while let Some(t) = it.next() {
+        let (opt, inline) = match t.split_once('=') {
+            Some((o, v)) if o.starts_with("--") => (o, Some(v.to_string())),
+            _ => (t.as_str(), None),
+        };
+        let mut val = || inline.clone().or_else(|| it.next().cloned());
+        match opt {
+            "-c" | "--command" => e.cmd = val(),
+            "-s" | "--short-option" => e.flags.extend(val().map(|v| format!("-{}", v))),
+            "-l" | "--long-option" => e.flags.extend(val().map(|v| format!("--{}", v))),
+            "-o" | "--old-option" => e.flags.extend(val().map(|v| format!("-{}", v))),
+            "-a" | "--arguments" => e.args = val(),
+            "-d" | "--description" => e.desc = val(),
+            "-n" | "--condition" => e.cond = val(),
+            "-w" | "--wraps" | "-p" | "--path" => {
+                val();
+            }
+            "-f" | "--no-files" => e.no_files = true,
+            "-F" | "--force-files" => e.force_files = true,
+            "-r" | "--require-parameter" => e.require = true,
+            "-x" | "--exclusive" => {
+                e.require = true;
+                e.no_files = true;
+            }
+            "-e" | "--erase" => return None,
+            _ => {}
+        }
+    }
+    Some(e)
+}
+
+/// The subcommands a condition limits an entry to, "" being the command itself.
+/// None if the condition can't be followed without fish
+fn levels(cond: &Option<String>) -> Option<Vec<String>> {
+    let c = match cond {
+        Some(c) => tokens(c),
+        None => return Some(vec![String::new()]),
+    };
+    match c.first().map(String::as_str) {
+        Some("__fish_use_subcommand" | "__fish_is_first_arg" | "__fish_is_first_token")
+            if c.len() == 1 =>
+        {
+            Some(vec![String::new()])
+        }
+        Some("__fish_seen_subcommand_from") => Some(c[1..].to_vec()),
+        _ => None,
+    }
+}
+
+/// Splits fish arguments into plain words, and commands run for more words.
+/// Commands are dropped unless they parse whole, their program is in allow,
+/// and they call no fish functions
+fn split_args(a: &str, allow: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut words = Vec::new();
+    let mut gens = Vec::new();
+    let mut rest = a;
+    while let Some(open) = rest.find('(') {
+        words.extend(tokens(&rest[..open]));
+        let mut depth = 0;
+        let close = rest[open..].char_indices().find_map(|(i, c)| {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            (depth == 0).then_some(open + i)
+        });
+        let close = match close {
+            Some(c) => c,
+            None => return (words, gens),
+        };
+        let inner = rest[open + 1..close].trim();
+        let prog = inner.split_whitespace().next().unwrap_or("");
+        let allowed = allow.iter().any(|a| a == prog) && !inner.contains("__fish");
+        if allowed && (parser::PExec, EOI).parse_s(inner).is_ok() {
+            gens.push(inner.to_string());
+        }
+        rest = &rest[close + 1..];
+    }
+    words.extend(tokens(rest));
+    words.retain(|w| !w.starts_with('$'));
+    (words, gens)
+}
+
+#[derive(Default)]
+struct Level {
+    args: Vec<String>,
+    gens: Vec<String>,
+    flags: BTreeMap<String, Data>,
+    desc: BTreeMap<String, Data>,
+    no_files: bool,
+    force_files: bool,
+}
+
+impl Level {
+    fn add(&mut self, e: &Entry, allow: &[String]) {
+        let (words, gens) = e
+            .args
+            .as_deref()
+            .map(|a| split_args(a, allow))
+            .unwrap_or_default();
+        let desc = e.desc.as_ref().map(|d| Data::Str(d.clone()));
+        if e.flags.is_empty() {
+            for w in &words {
+                if let Some(d) = &desc {
+                    self.desc.insert(w.clone(), d.clone());
+                }
+            }
+            self.args.extend(words);
+            self.gens.extend(gens);
+            self.no_files |= e.no_files;
+            self.force_files |= e.force_files;
+            return;
+        }
+        let value = match (&e.args, e.require) {
+            (Some(_), _) => Data::List(words.into_iter().map(Data::Str).collect()),
+            (None, true) if e.no_files => Data::List(Vec::new()),
+            (None, true) => Data::Str("file".to_string()),
+            (None, false) => Data::Str("none".to_string()),
+        };
+        for f in &e.flags {
+            self.flags.insert(f.clone(), value.clone());
+            if let Some(d) = &desc {
+                self.desc.insert(f.clone(), d.clone());
+            }
+        }
+    }
+
+    fn into_data(self, files: bool) -> BTreeMap<String, Data> {
+        let strs = |v: Vec<String>| Data::List(v.into_iter().map(Data::Str).collect());
+        let mut m = BTreeMap::new();
+        if !self.args.is_empty() {
+            m.insert("args".to_string(), strs(self.args));
+        }
+        if !self.gens.is_empty() {
+            m.insert("gen".to_string(), strs(self.gens));
+        }
+        if !self.flags.is_empty() {
+            m.insert("flags".to_string(), Data::Map(self.flags));
+        }
+        if !self.desc.is_empty() {
+            m.insert("desc".to_string(), Data::Map(self.desc));
+        }
+        if self.force_files || (files && !self.no_files) {
+            m.insert("files".to_string(), Data::Str("file".to_string()));
+        }
+        m
+    }
+}
+
+/// Builds the spec for cmd from the text of a fish completion file,
+/// running only the generator programs in allow
+pub fn parse_spec(s: &str, cmd: &str, allow: &[String]) -> Data {
+    let mut top = Level::default();
+    let mut subs: BTreeMap<String, Level> = BTreeMap::new();
+    //"complete -c cmd -f" with no condition stops files everywhere
+    let mut files = true;
+    for line in logical_lines(s) {
+        let e = match entry(&tokens(&line)) {
+            Some(e) if e.cmd.as_deref() == Some(cmd) => e,
+            _ => continue,
+        };
+        let lvs = match levels(&e.cond) {
+            Some(l) => l,
+            None => continue,
+        };
+        if e.cond.is_none() && e.flags.is_empty() && e.no_files {
+            files = false;
+        }
+        for l in lvs {
+            match l.is_empty() {
+                true => top.add(&e, allow),
+                false => subs.entry(l).or_default().add(&e, allow),
+            }
+        }
+    }
+    let mut res = top.into_data(files);
+    if !subs.is_empty() {
+        let subs = subs
+            .into_iter()
+            .map(|(k, l)| (k, Data::Map(l.into_data(files))))
+            .collect();
+        res.insert("sub".to_string(), Data::Map(subs));
+    }
+    Data::Map(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::comp_spec::complete;
+    use crate::menu::Candidate;
+
+    fn texts(v: Vec<Candidate>) -> Vec<String> {
+        v.into_iter().map(|c| c.text).collect()
+    }
+
+    fn w(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn fish_tokens() {
+        assert_eq!(
+            tokens(r#"complete -c x -d 'it'\''s' -a "a \"b\"" c\ d # note"#),
+            ["complete", "-c", "x", "-d", "it's", "-a", "a \"b\"", "c d"]
+        );
+        let a = "main (git branch) (__fish_x) (git log; rm x) $v";
+        assert_eq!(split_args(a, &[]), (w(&["main"]), w(&[])));
+        assert_eq!(
+            split_args(a, &w(&["git", "__fish_x"])),
+            (w(&["main"]), w(&["git branch"]))
+        );
+    }
+
+    #[test]
+    fn gitlite_file() {
+        let mut store = Store::new();
+        let text = include_str!("../tests/fish/gitlite.fish");
+        assert!(!parse_spec(text, "gitlite", &[])
+            .to_string()
+            .contains("--list"));
+        let sp = parse_spec(text, "gitlite", &w(&["gitlite"]));
+
+        let r = complete(&sp, &w(&["gitlite"]), "", &mut store).unwrap();
+        assert_eq!(
+            r[1].desc.as_deref(),
+            Some("Switch branches or restore files")
+        );
+        assert_eq!(texts(r), ["add", "checkout", "switch", "status", "log"]);
+        let r = complete(&sp, &w(&["gitlite", "--color"]), "", &mut store).unwrap();
+        assert_eq!(texts(r), ["always", "never", "auto"]);
+        let r = complete(&sp, &w(&["gitlite", "add"]), "-", &mut store).unwrap();
+        assert_eq!(texts(r), ["--patch", "-p", "--color", "--version", "-C"]);
+
+        let sub = |s: &str| match &sp {
+            Data::Map(m) => match m.get("sub") {
+                Some(Data::Map(m)) => m.get(s).cloned(),
+                _ => None,
+            },
+            _ => None,
+        };
+        let co = sub("checkout").unwrap().to_string();
+        assert!(co.contains("gitlite branch --list"), "{}", co);
+        assert!(!co.contains("files"), "{}", co);
+        assert!(sub("add").unwrap().to_string().contains("files"));
+    }
+
+    #[test]
+    fn mytool_file() {
+        let mut store = Store::new();
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fish");
+        store.let_set("RU_FISH_COMPLETE".to_string(), Data::Str(dir.to_string()));
+        assert!(spec_for("nothing_here", &store).is_none());
+        let sp = spec_for("mytool", &store).unwrap();
+
+        let r = complete(&sp, &w(&["mytool"]), "-", &mut store).unwrap();
+        assert_eq!(texts(r), ["--level", "--mode", "--output", "-o", "-quiet"]);
+        let r = complete(&sp, &w(&["mytool", "--level"]), "", &mut store).unwrap();
+        assert_eq!(texts(r), ["1", "2", "3"]);
+        let r = complete(&sp, &w(&["mytool", "-quiet", "--mode"]), "", &mut store).unwrap();
+        assert_eq!(texts(r), ["fast", "slow"]);
+        let r = complete(&sp, &w(&["mytool"]), "zzz", &mut store).unwrap();
+        assert_eq!(r[0].text, "build");
+        assert_eq!(r[1].desc.as_deref(), Some("Run \"it\""));
+    }
+}
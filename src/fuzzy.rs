@@ -0,0 +1,93 @@
+//! Subsequence matching for completions, so "tb_comp" finds "tab_complete.rs"
+
+/// Bonus making sure prefix matches, ignoring case as the match does, always rank first
+const PREFIX_BONUS: i64 = 1000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FuzzyMatch {
+    pub score: i64,
+    /// Byte indices in the candidate of each matched char
+    pub indices: Vec<usize>,
+}
+
+fn is_boundary(c: Option<char>) -> bool {
+    match c {
+        None => true,
+        Some(c) => "/_-. ".contains(c),
+    }
+}
+
+/// Matches when every char of pat appears in cand, in order.
+/// Case is ignored unless pat contains a capital.
+pub fn fuzzy_match(pat: &str, cand: &str) -> Option<FuzzyMatch> {
+    let case = pat.chars().any(char::is_uppercase);
+    let eq = |a: char, b: char| match case {
+        true => a == b,
+        false => a.to_lowercase().eq(b.to_lowercase()),
+    };
+
+    let mut indices = Vec::new();
+    let mut score = 0;
+    let mut prev: Option<char> = None;
+    let mut last: Option<usize> = None;
+    let mut it = cand.char_indices();
+    'pat: for p in pat.chars() {
+        for (i, c) in &mut it {
+            let before = prev;
+            prev = Some(c);
+            if !eq(p, c) {
+                continue;
+            }
+            score += 1;
+            match last {
+                Some(l) if cand[l..i].chars().count() == 1 => score += 5,
+                Some(_) => score -= 1,
+                None => {}
+            }
+            if is_boundary(before) {
+                score += 3;
+            }
+            indices.push(i);
+            last = Some(i);
+            continue 'pat;
+        }
+        return None;
+    }
+    let mut cs = cand.chars();
+    if pat.chars().all(|p| cs.next().is_some_and(|c| eq(p, c))) {
+        score += PREFIX_BONUS;
+    }
+    score -= cand.chars().count() as i64 / 8;
+    Some(FuzzyMatch { score, indices })
+}
+
+/// Keeps only the strings matching pat, best first
+pub fn rank<I: IntoIterator<Item = String>>(pat: &str, it: I) -> Vec<String> {
+    let mut v: Vec<(i64, String)> = it
+        .into_iter()
+        .filter_map(|s| fuzzy_match(pat, &s).map(|m| (m.score, s)))
+        .collect();
+    v.sort_by_key(|(sc, _)| std::cmp::Reverse(*sc));
+    v.into_iter().map(|(_, s)| s).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn subsequence() {
+        let m = fuzzy_match("tb_comp", "src/tab_complete.rs");
+        assert_eq!(m.map(|m| m.indices), Some(vec![4, 6, 7, 8, 9, 10, 11]));
+        assert!(fuzzy_match("cgo", "cargo").is_some());
+        assert!(fuzzy_match("cgx", "cargo").is_none());
+        assert!(fuzzy_match("Ca", "cargo").is_none());
+    }
+
+    #[test]
+    fn prefix_first() {
+        let v = vec!["xcargo".to_string(), "cat".to_string(), "car".to_string()];
+        assert_eq!(rank("car", v), ["car", "xcargo"]);
+        let v = vec!["a.doc".to_string(), "Documents".to_string()];
+        assert_eq!(rank("doc", v), ["Documents", "a.doc"]);
+    }
+}
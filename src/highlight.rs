@@ -1,40 +1,469 @@
 //use crate::parser::Letter;
+use crate::data::Data;
+use crate::parser;
 use crate::partial::*;
+use crate::path_index;
+use crate::store::Store;
 use transliterate::parser::BackTo;
 //use bogobble::common::*;
 use bogobble::*;
-use std::collections::BTreeMap;
+use err_tools::*;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Write;
+use std::path::PathBuf;
+
+/// Themes that don't need a file.
+/// Others are read from "~/.config/rushell/themes/<name>.theme"
+const THEMES: &[(&str, &str)] = &[
+    ("default", ""),
+    (
+        "mono",
+        "keyword:bold,symbol:bold,var:underline,quoted:italic,esc:bold,comment:italic",
+    ),
+    (
+        "solarized",
+        "keyword:#b58900 bold,symbol:#268bd2,var:#d33682,string:#859900,lit:#2aa198,\
+         quoted:#2aa198,esc:#6c71c4,comment:#586e75 italic",
+    ),
+];
+
+/// How many colours the terminal can show
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Depth {
+    None,
+    Basic,
+    Ansi256,
+    True,
+}
+
+impl Depth {
+    /// From RU_COLORS if set ("none", "16", "256" or "true"), else TERM and COLORTERM
+    pub fn from_store(store: &Store) -> Self {
+        if let Some(d) = store.get("RU_COLORS") {
+            match d.to_string().as_str() {
+                "none" | "0" => return Depth::None,
+                "16" | "8" => return Depth::Basic,
+                "256" => return Depth::Ansi256,
+                "true" | "24bit" | "truecolor" => return Depth::True,
+                _ => {}
+            }
+        }
+        if std::env::var_os("NO_COLOR").is_some() {
+            return Depth::None;
+        }
+        let term = std::env::var("TERM").unwrap_or_default();
+        let cterm = std::env::var("COLORTERM").unwrap_or_default();
+        match (term.as_str(), cterm.as_str()) {
+            ("dumb", _) => Depth::None,
+            (_, "truecolor" | "24bit") => Depth::True,
+            (t, c) if t.contains("256") || !c.is_empty() => Depth::Ansi256,
+            _ => Depth::Basic,
+        }
+    }
+}
+
+/// The usual xterm values of the 16 basic colours
+const BASIC_RGB: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// The levels of each part in the 6x6x6 cube of 256 colours
+const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+const BASIC_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Color {
+    /// 0-7 normal, 8-15 light
+    Basic(u8),
+    Ansi(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    pub fn parse(s: &str) -> Option<Color> {
+        if let Some(hex) = s.strip_prefix('#') {
+            let n = u32::from_str_radix(hex, 16)
+                .ok()
+                .filter(|_| hex.len() == 6)?;
+            return Some(Color::Rgb((n >> 16) as u8, (n >> 8) as u8, n as u8));
+        }
+        if let Ok(n) = s.parse::<u8>() {
+            return Some(Color::Ansi(n));
+        }
+        let (light, name) = match s.strip_prefix("light") {
+            Some(n) => (8, n),
+            None => (0, s),
+        };
+        let n = BASIC_NAMES.iter().position(|b| *b == name)? as u8;
+        Some(Color::Basic(n + light))
+    }
+
+    fn rgb(&self) -> (u8, u8, u8) {
+        match *self {
+            Color::Basic(n) => BASIC_RGB[n as usize % 16],
+            Color::Ansi(n) if n < 16 => BASIC_RGB[n as usize],
+            Color::Ansi(n) if n < 232 => {
+                let n = (n - 16) as usize;
+                (LEVELS[n / 36], LEVELS[(n / 6) % 6], LEVELS[n % 6])
+            }
+            Color::Ansi(n) => {
+                let g = 8 + 10 * (n - 232);
+                (g, g, g)
+            }
+            Color::Rgb(r, g, b) => (r, g, b),
+        }
+    }
+
+    /// The nearest colour the terminal can show
+    pub fn fit(self, depth: Depth) -> Color {
+        match (self, depth) {
+            (Color::Rgb(r, g, b), Depth::Ansi256) => {
+                let c6 = |c: u8| {
+                    (0..6)
+                        .min_by_key(|n| (LEVELS[*n] as i32 - c as i32).abs())
+                        .unwrap_or(0)
+                };
+                match r == g && g == b {
+                    true => Color::Ansi(232 + (r.saturating_sub(8) / 10).min(23)),
+                    false => Color::Ansi((16 + 36 * c6(r) + 6 * c6(g) + c6(b)) as u8),
+                }
+            }
+            (Color::Rgb(..) | Color::Ansi(_), Depth::Basic) => {
+                let (r, g, b) = self.rgb();
+                let dist = |(br, bg, bb): &(u8, u8, u8)| {
+                    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+                    d(r, *br) + d(g, *bg) + d(b, *bb)
+                };
+                let n = (0..16).min_by_key(|n| dist(&BASIC_RGB[*n])).unwrap_or(7);
+                Color::Basic(n as u8)
+            }
+            (c, _) => c,
+        }
+    }
+
+    fn code(&self, bg: bool) -> String {
+        let base = if bg { 40 } else { 30 };
+        match self {
+            Color::Basic(n) if *n < 8 => format!("{}", base + n),
+            Color::Basic(n) => format!("{}", base + 60 + (n - 8)),
+            Color::Ansi(n) => format!("{};5;{}", base + 8, n),
+            Color::Rgb(r, g, b) => format!("{};2;{};{};{}", base + 8, r, g, b),
+        }
+    }
+}
+
+/// A colour and attributes, written as words like "#ffaa00 bold" or "bg:236 italic"
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub reverse: bool,
+}
+
+impl Style {
+    pub fn parse(s: &str) -> anyhow::Result<Style> {
+        let mut res = Style::default();
+        for w in s.split_whitespace() {
+            match w {
+                "bold" => res.bold = true,
+                "italic" => res.italic = true,
+                "underline" => res.underline = true,
+                "reverse" => res.reverse = true,
+                "default" | "none" => {}
+                w => match w.strip_prefix("bg:") {
+                    Some(b) => {
+                        res.bg = Some(Color::parse(b).e_string(format!("bad colour {}", w))?)
+                    }
+                    None => res.fg = Some(Color::parse(w).e_string(format!("bad style {}", w))?),
+                },
+            }
+        }
+        Ok(res)
+    }
+
+    /// The escape setting this style, after clearing any other
+    pub fn escape(&self, depth: Depth) -> String {
+        if depth == Depth::None {
+            return String::new();
+        }
+        let mut codes = vec!["0".to_string()];
+        if self.bold {
+            codes.push("1".to_string());
+        }
+        if self.italic {
+            codes.push("3".to_string());
+        }
+        if self.underline {
+            codes.push("4".to_string());
+        }
+        if self.reverse {
+            codes.push("7".to_string());
+        }
+        if let Some(c) = self.fg {
+            codes.push(c.fit(depth).code(false));
+        }
+        if let Some(c) = self.bg {
+            codes.push(c.fit(depth).code(true));
+        }
+        format!("\x1b[{}m", codes.join(";"))
+    }
+}
+
+/// Styles for items that depend on what exists, before any theme
+const SEMANTIC: &[(&str, &str)] = &[
+    ("missing_command", "lightred"),
+    ("missing_var", "red"),
+    ("path", "lightgreen underline"),
+];
+
+/// What exists, so commands, paths and vars can be coloured by it.
+/// Made again after each command, as that may change any of them
+#[derive(Debug, Clone, Default)]
+pub struct Known {
+    commands: BTreeSet<String>,
+    vars: BTreeSet<String>,
+    /// Paths already looked up, and whether they exist (or can run)
+    paths: RefCell<BTreeMap<(String, bool), bool>>,
+}
+
+impl Known {
+    pub fn from_store(store: &Store) -> Self {
+        let mut commands = path_index::programs(store);
+        for l in [parser::BUILTINS, parser::ASSIGNERS, parser::KEYWORDS] {
+            commands.extend(l.iter().map(|s| s.to_string()));
+        }
+        store.do_with("RU_ALIAS", |d| {
+            if let Data::Map(m) = d {
+                commands.extend(m.keys().cloned());
+            }
+        });
+        let mut vars = BTreeSet::new();
+        store.for_each(|k, _, _| {
+            vars.insert(k.to_string());
+        });
+        Known {
+            commands,
+            vars,
+            paths: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    fn path_ok(&self, p: &str, exec: bool) -> bool {
+        let key = (p.to_string(), exec);
+        if let Some(b) = self.paths.borrow().get(&key) {
+            return *b;
+        }
+        let full = match p.strip_prefix("~/") {
+            Some(rest) => PathBuf::from(std::env::var("HOME").unwrap_or_default()).join(rest),
+            None => PathBuf::from(p),
+        };
+        let res = match exec {
+            true => path_index::is_executable(&full),
+            false => full.exists(),
+        };
+        self.paths.borrow_mut().insert(key, res);
+        res
+    }
+
+    fn command_ok(&self, c: &str) -> bool {
+        match c.contains('/') {
+            true => self.path_ok(c, true),
+            false => self.commands.contains(c),
+        }
+    }
+
+    fn var_ok(&self, v: &str) -> bool {
+        self.vars.contains(v) || std::env::var_os(v).is_some()
+    }
+}
+
+/// The unescaped word at the start of s, and how many bytes of s it took
+fn word(s: &str) -> (String, usize) {
+    let mut res = String::new();
+    let mut it = s.char_indices();
+    while let Some((i, c)) = it.next() {
+        match c {
+            '\\' => match it.next() {
+                Some((_, e)) => res.push(e),
+                None => return (res, s.len()),
+            },
+            c if " \t\n#&$|^{}()[]\"<>;".contains(c) => return (res, i),
+            c => res.push(c),
+        }
+    }
+    (res, s.len())
+}
+
+/// The name of the style for an item, checking what it names where that matters
+fn semantic(k: &Known, i: Item, line: &str, pos: usize) -> &'static str {
+    let rest = line.get(pos..).unwrap_or("");
+    match i {
+        Item::Command => match word(rest.trim_start()).0.as_str() {
+            "" => "command",
+            w if k.command_ok(w) => "command",
+            _ => "missing_command",
+        },
+        Item::Var => match rest.strip_prefix('$') {
+            Some(v) if !v.starts_with('{') => {
+                let (name, _) = word(v);
+                match name.is_empty() || k.var_ok(&name) {
+                    true => "var",
+                    false => "missing_var",
+                }
+            }
+            _ => "var",
+        },
+        Item::String => {
+            //Only whole words can be paths, not part of "a$b"
+            let starts = matches!(line[..pos].chars().last(), None | Some(' ' | '\t' | '\n'));
+            let (w, n) = word(rest);
+            let ends = matches!(
+                rest[n..].chars().next(),
+                None | Some(' ' | '\t' | '\n' | ';' | '|' | '&' | ')' | ']' | '}' | '>')
+            );
+            match starts && ends && !w.is_empty() && k.path_ok(&w, false) {
+                true => "path",
+                false => "string",
+            }
+        }
+        i => i.name(),
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Highlight {
     mp: BTreeMap<String, String>,
+    depth: Depth,
+    known: Option<Known>,
 }
 impl Highlight {
     pub fn empty() -> Self {
         Highlight {
             mp: BTreeMap::new(),
+            depth: Depth::Basic,
+            known: None,
+        }
+    }
+
+    pub fn with_known(mut self, k: Known) -> Self {
+        self.known = Some(k);
+        self
+    }
+
+    /// Adds "item:style" pairs split by commas or lines.
+    /// A value holding an escape char is used as it is
+    fn add_str(&mut self, s: &str) -> anyhow::Result<()> {
+        let s: Vec<&str> = s
+            .lines()
+            .map(|l| l.trim())
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .collect();
+        let joined = s.join(",");
+        let l = HList.parse_s(&joined).map_err(|e| e.strung())?;
+        for (k, v) in l {
+            let v = match v.contains('\x1b') {
+                true => v.to_string(),
+                false => Style::parse(v)
+                    .map_err(|e| SgError(format!("{} : {}", k, e)))?
+                    .escape(self.depth),
+            };
+            self.mp.insert(k, v);
+        }
+        Ok(())
+    }
+
+    /// The theme named by RU_THEME, with RU_HIGHLIGHT on top
+    pub fn from_store(store: &Store) -> anyhow::Result<Self> {
+        let mut res = Highlight {
+            mp: BTreeMap::new(),
+            depth: Depth::from_store(store),
+            known: None,
+        };
+        for (k, v) in SEMANTIC {
+            res.add_str(&format!("{}:{}", k, v))?;
         }
+        if let Some(t) = store.get("RU_THEME") {
+            res.add_str(&theme_text(&t.to_string())?)?;
+        }
+        if let Some(h) = store.get("RU_HIGHLIGHT") {
+            res.add_str(&h.to_string())?;
+        }
+        Ok(res)
     }
-    pub fn from_str(s: &str) -> anyhow::Result<Self> {
-        let l = HList.parse_s(s).map_err(|e| e.strung())?;
-        let mp = l.into_iter().map(|(k, v)| (k, v.to_string())).collect();
-        Ok(Highlight { mp })
+
+    /// The escape for the bracket matching the one at the cursor, None if no colours
+    pub fn match_style(&self) -> Option<String> {
+        match (self.depth, self.mp.get("match")) {
+            (Depth::None, _) => None,
+            (_, Some(m)) => Some(m.clone()),
+            (d, None) => Some(
+                Style {
+                    reverse: true,
+                    ..Style::default()
+                }
+                .escape(d),
+            ),
+        }
     }
 
     pub fn highlight<'a>(&self, s: &'a str) -> Result<String, bogobble::PErr<'a>> {
         use transliterate::parser::*;
-        Lines.ss_convert(s, self)
+        Lines.ss_convert(s, &Painter { hl: self, line: s })
     }
 }
 
-impl BackTo for Highlight {}
+pub fn theme_text(name: &str) -> anyhow::Result<String> {
+    if let Some((_, t)) = THEMES.iter().find(|(n, _)| *n == name) {
+        return Ok(t.to_string());
+    }
+    let mut p = std::path::PathBuf::from(std::env::var("HOME").unwrap_or_default());
+    p.push(".config/rushell/themes");
+    p.push(format!("{}.theme", name));
+    std::fs::read_to_string(&p).map_err(|e| SgError(format!("theme {} : {}", name, e)).into())
+}
 
-impl ParseMark for Highlight {
-    fn mark(&self, i: Item, s: &mut String, _: Option<usize>) {
-        match self.mp.get(i.name()) {
+/// Marks out one line, knowing its text so items can be looked up
+struct Painter<'a> {
+    hl: &'a Highlight,
+    line: &'a str,
+}
+
+impl BackTo for Painter<'_> {}
+
+impl ParseMark for Painter<'_> {
+    fn mark(&self, i: Item, s: &mut String, pos: Option<usize>) {
+        if self.hl.depth == Depth::None {
+            return;
+        }
+        let name = match (&self.hl.known, pos) {
+            (Some(k), Some(p)) => semantic(k, i, self.line, p),
+            _ => i.name(),
+        };
+        match self.hl.mp.get(name) {
             Some(r) => write!(s, "{}", r),
-            None => write!(s, "{}", i),
+            None => write!(s, "{}{}", termion::style::Reset, i),
         }
         .ok();
     }
@@ -44,5 +473,70 @@ parser! { (HList->Vec<(String,&'a str)>)
     sep_star(HItem,",")
 }
 parser! {(HItem->(String,&'a str))
-    (common::Ident,":",not(",").star()).map(|(a,_,b)|(a,b))
+    (ws__(common::Ident),":",not(",").star()).map(|(a,_,b)|(a,b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn styles_fit_depth() {
+        let s = Style::parse("#ff0000 bold bg:236").unwrap();
+        assert_eq!(s.escape(Depth::True), "\x1b[0;1;38;2;255;0;0;48;5;236m");
+        assert_eq!(s.escape(Depth::Ansi256), "\x1b[0;1;38;5;196;48;5;236m");
+        assert_eq!(s.escape(Depth::Basic), "\x1b[0;1;91;40m");
+        assert_eq!(s.escape(Depth::None), "");
+        assert_eq!(
+            Style::parse("lightblue italic underline")
+                .unwrap()
+                .escape(Depth::Basic),
+            "\x1b[0;3;4;94m"
+        );
+        assert!(Style::parse("#12345").is_err());
+        assert!(Style::parse("purpel").is_err());
+    }
+
+    #[test]
+    fn themes_and_overrides() {
+        let store = Store::new();
+        store.let_set(
+            "RU_COLORS".to_string(),
+            crate::data::Data::Str("256".to_string()),
+        );
+        store.let_set(
+            "RU_THEME".to_string(),
+            crate::data::Data::Str("solarized".to_string()),
+        );
+        store.let_set(
+            "RU_HIGHLIGHT".to_string(),
+            crate::data::Data::Str("keyword:red".to_string()),
+        );
+        let h = Highlight::from_store(&store).unwrap();
+        assert_eq!(h.mp.get("keyword").unwrap(), "\x1b[0;31m");
+        assert_eq!(h.mp.get("comment").unwrap(), "\x1b[0;3;38;5;60m");
+        store.let_set(
+            "RU_THEME".to_string(),
+            crate::data::Data::Str("nothing".to_string()),
+        );
+        assert!(Highlight::from_store(&store).is_err());
+    }
+
+    #[test]
+    fn semantic_items() {
+        let store = Store::new();
+        store.let_set("defined".to_string(), Data::Str("x".to_string()));
+        store.let_set("PATH".to_string(), Data::Str(String::new()));
+        let k = Known::from_store(&store);
+        let at = |line: &str, i: Item, w: &str| semantic(&k, i, line, line.find(w).unwrap());
+        assert_eq!(at("cd src", Item::Command, "cd"), "command");
+        assert_eq!(
+            at("nothere src", Item::Command, "nothere"),
+            "missing_command"
+        );
+        assert_eq!(at("cat src", Item::String, "src"), "path");
+        assert_eq!(at("cat src$x", Item::String, "src"), "string");
+        assert_eq!(at("cat nothere", Item::String, "nothere"), "string");
+        assert_eq!(at("echo $defined", Item::Var, "$"), "var");
+        assert_eq!(at("echo $undefined_x", Item::Var, "$"), "missing_var");
+    }
 }
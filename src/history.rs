@@ -0,0 +1,656 @@
+//! History is kept in one file per month under "~/.config/rushell/history",
+//! with how long each run took in a "timing" file beside it
+use crate::computed::fmt_duration;
+use crate::data::Data;
+use crate::store::Store;
+use crate::timing::Times;
+use bogobble::traits::*;
+use chrono::*;
+use regex::Regex;
+use ru_history::parse::{File, Item};
+use ru_history::HistoryStore;
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+pub const DEFAULT_MONTHS: u32 = 2;
+
+/// Counts requests to reload history from disk, after files are rewritten
+/// or to merge in entries from other sessions
+static RELOADS: AtomicUsize = AtomicUsize::new(0);
+
+/// One command in one directory, as read from the history files
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub cmd: String,
+    pub dir: String,
+    pub recent: u64,
+    pub hits: usize,
+}
+
+/// One run of a command, and how it went
+#[derive(Clone, Debug, PartialEq)]
+pub struct Timing {
+    pub cmd: String,
+    pub when: u64,
+    pub times: Times,
+    pub status: i32,
+}
+
+pub fn history_path() -> PathBuf {
+    let mut tdir = PathBuf::from(std::env::var("HOME").unwrap_or_default());
+    tdir.push(".config/rushell/history");
+    tdir
+}
+
+fn on_year_month(p: &Path, y: i32, m: u32) -> PathBuf {
+    let dt_s = format!("history_{}_{}.fd", y, m);
+    p.join(&dt_s)
+}
+
+/// The timing file that goes with a history file
+fn timing_file(p: &Path) -> PathBuf {
+    let name = p.file_name().map(|f| f.to_string_lossy().to_string());
+    let name = name.unwrap_or_default().replacen("history_", "timing_", 1);
+    p.with_file_name(name)
+}
+
+fn year_month(t: SystemTime) -> (i32, u32) {
+    let dt: DateTime<offset::Local> = DateTime::from(t);
+    (dt.year(), dt.month())
+}
+
+/// The files for the last n months, oldest first
+fn month_files(months: u32) -> Vec<PathBuf> {
+    let (y, m) = year_month(SystemTime::now());
+    let path = history_path();
+    let mut res = Vec::new();
+    for n in 1..=months {
+        let sub = months - n;
+        let back = (12 + m - 1) as i32 - sub as i32;
+        let y2 = y - 1 + back.div_euclid(12);
+        let m2 = back.rem_euclid(12) as u32 + 1;
+        res.push(on_year_month(&path, y2, m2));
+    }
+    res
+}
+
+/// Every history file on disk, whatever month it is for
+fn all_files() -> anyhow::Result<Vec<PathBuf>> {
+    files_starting("history_")
+}
+
+fn files_starting(prefix: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let rd = match std::fs::read_dir(history_path()) {
+        Ok(rd) => rd,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut res = Vec::new();
+    for e in rd {
+        let p = e?.path();
+        let fname = p.file_name().map(|f| f.to_string_lossy().to_string());
+        if let Some(f) = fname {
+            if f.starts_with(prefix) && f.ends_with(".fd") {
+                res.push(p);
+            }
+        }
+    }
+    res.sort();
+    Ok(res)
+}
+
+/// Reads RU_HISTORY_MONTHS, the number of months of history to load
+pub fn months(store: &Store) -> u32 {
+    store
+        .get("RU_HISTORY_MONTHS")
+        .and_then(|d| d.to_string().trim().parse().ok())
+        .unwrap_or(DEFAULT_MONTHS)
+}
+
+pub fn reloads() -> usize {
+    RELOADS.load(Ordering::SeqCst)
+}
+
+pub fn request_reload() {
+    RELOADS.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Rules deciding which commands are never written to history.
+/// Set up by:
+/// * RU_HISTORY_IGNORE_SPACE : skip lines starting with a space (default true)
+/// * RU_HISTORY_DEDUPE : skip a command repeating the previous one (default true)
+/// * RU_HISTORY_MAX_LEN : skip commands longer than this
+/// * RU_HISTORY_IGNORE : a regex or list of regexes to skip
+pub struct IgnoreRules {
+    space: bool,
+    pub dedupe: bool,
+    max_len: Option<usize>,
+    patterns: Vec<Regex>,
+}
+
+impl IgnoreRules {
+    pub fn from_store(store: &Store) -> anyhow::Result<Self> {
+        let flag = |k: &str| store.get(k).map(|d| d.is_true()).unwrap_or(true);
+        let max_len = match store.get("RU_HISTORY_MAX_LEN") {
+            Some(d) => Some(d.to_string().trim().parse()?),
+            None => None,
+        };
+        let mut patterns = Vec::new();
+        match store.get("RU_HISTORY_IGNORE") {
+            Some(Data::List(l)) => {
+                for p in l {
+                    patterns.push(Regex::new(&p.to_string())?);
+                }
+            }
+            Some(d) => patterns.push(Regex::new(&d.to_string())?),
+            None => {}
+        }
+        Ok(IgnoreRules {
+            space: flag("RU_HISTORY_IGNORE_SPACE"),
+            dedupe: flag("RU_HISTORY_DEDUPE"),
+            max_len,
+            patterns,
+        })
+    }
+
+    pub fn ignores(&self, cmd: &str) -> bool {
+        if self.space && cmd.starts_with(' ') {
+            return true;
+        }
+        if let Some(n) = self.max_len {
+            if cmd.chars().count() > n {
+                return true;
+            }
+        }
+        self.patterns.iter().any(|p| p.is_match(cmd))
+    }
+}
+
+/// Holds an exclusive lock on the history folder until dropped.
+/// Every session appending or rewriting history files takes this first.
+pub struct HistoryLock(std::fs::File);
+
+impl HistoryLock {
+    pub fn take() -> anyhow::Result<Self> {
+        let hpath = history_path();
+        std::fs::create_dir_all(&hpath)?;
+        let f = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(hpath.join(".lock"))?;
+        match unsafe { libc::flock(f.as_raw_fd(), libc::LOCK_EX) } {
+            0 => Ok(HistoryLock(f)),
+            _ => Err(std::io::Error::last_os_error().into()),
+        }
+    }
+}
+
+impl Drop for HistoryLock {
+    fn drop(&mut self) {
+        unsafe { libc::flock(self.0.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
+
+pub fn load_history(months: u32, hist: &mut HistoryStore) -> anyhow::Result<()> {
+    for p in month_files(months) {
+        match std::fs::read_to_string(p) {
+            Ok(s) => ru_history::parse::parse_onto(hist, &s)?,
+            Err(e) => match e.kind() {
+                ErrorKind::NotFound => continue,
+                _ => return Err(e.into()),
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Appends any unsaved commands to this month's file.
+/// They are written with a single call under the history lock,
+/// so concurrent sessions cannot interleave partial entries.
+pub fn save_history(hs: &mut HistoryStore) -> anyhow::Result<()> {
+    let mut buf: Vec<u8> = Vec::new();
+    hs.write_to(&mut buf, false)?;
+    if buf.is_empty() {
+        return Ok(());
+    }
+    let (y, m) = year_month(SystemTime::now());
+    let _lock = HistoryLock::take()?;
+    let path = on_year_month(&history_path(), y, m);
+    let mut f = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    f.write_all(&buf)?;
+    f.sync_data()?;
+    Ok(())
+}
+
+/// Appends how a run went to this month's timing file
+pub fn save_timing(t: &Timing) -> anyhow::Result<()> {
+    let (y, m) = year_month(SystemTime::now());
+    let _lock = HistoryLock::take()?;
+    let path = timing_file(&on_year_month(&history_path(), y, m));
+    let mut f = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    let mut buf = Vec::new();
+    write_timings(&mut buf, std::slice::from_ref(t))?;
+    f.write_all(&buf)?;
+    Ok(())
+}
+
+pub fn write_timings<W: Write>(w: &mut W, ts: &[Timing]) -> std::io::Result<()> {
+    for t in ts {
+        writeln!(
+            w,
+            "t{},w{},u{},s{},x{},c{}",
+            t.when,
+            t.times.wall.as_millis(),
+            t.times.user.as_millis(),
+            t.times.sys.as_millis(),
+            t.status,
+            quoted(&t.cmd)
+        )?;
+    }
+    Ok(())
+}
+
+fn unquoted(s: &str) -> Option<String> {
+    let s = s.strip_prefix('"')?.strip_suffix('"')?;
+    let mut res = String::new();
+    let mut it = s.chars();
+    while let Some(c) = it.next() {
+        match c {
+            '\\' => match it.next()? {
+                'n' => res.push('\n'),
+                c => res.push(c),
+            },
+            c => res.push(c),
+        }
+    }
+    Some(res)
+}
+
+/// Reads timing lines, skipping any that don't make sense
+pub fn parse_timings(s: &str) -> Vec<Timing> {
+    let line = |l: &str| -> Option<Timing> {
+        let (nums, cmd) = l.split_once(",c")?;
+        let mut f = nums.split(',');
+        let mut num = |c: char| f.next()?.strip_prefix(c)?.parse::<i64>().ok();
+        let (when, w, u, s, x) = (num('t')?, num('w')?, num('u')?, num('s')?, num('x')?);
+        let ms = |n: i64| Duration::from_millis(n as u64);
+        Some(Timing {
+            cmd: unquoted(cmd)?,
+            when: when as u64,
+            times: Times {
+                wall: ms(w),
+                user: ms(u),
+                sys: ms(s),
+            },
+            status: x as i32,
+        })
+    };
+    s.lines().filter_map(line).collect()
+}
+
+pub fn read_timings(months: u32) -> Vec<Timing> {
+    let mut res = Vec::new();
+    for p in month_files(months) {
+        if let Ok(s) = std::fs::read_to_string(timing_file(&p)) {
+            res.extend(parse_timings(&s));
+        }
+    }
+    res
+}
+
+pub fn parse_entries(s: &str) -> anyhow::Result<Vec<Entry>> {
+    let items = File.parse_s(s).map_err(|e| e.strung())?;
+    let mut res = Vec::new();
+    let (mut cmd, mut recent, mut hits) = (None, 0, 0);
+    for it in items {
+        match it {
+            Item::Cmd(c) => cmd = Some(c),
+            Item::Recent(r) => recent = r,
+            Item::Hits(h) => hits = h,
+            Item::Path(dir) => {
+                if let Some(c) = &cmd {
+                    res.push(Entry {
+                        cmd: c.clone(),
+                        dir,
+                        recent,
+                        hits,
+                    });
+                }
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Combines entries for the same command and directory, oldest first
+fn merge_entries<I: IntoIterator<Item = Entry>>(it: I) -> Vec<Entry> {
+    let mut mp: BTreeMap<(String, String), Entry> = BTreeMap::new();
+    for e in it {
+        match mp.get_mut(&(e.cmd.clone(), e.dir.clone())) {
+            Some(old) => {
+                old.hits += e.hits;
+                old.recent = old.recent.max(e.recent);
+            }
+            None => {
+                mp.insert((e.cmd.clone(), e.dir.clone()), e);
+            }
+        }
+    }
+    let mut res: Vec<Entry> = mp.into_values().collect();
+    res.sort_by_key(|e| e.recent);
+    res
+}
+
+pub fn read_entries(months: u32) -> anyhow::Result<Vec<Entry>> {
+    let mut res = Vec::new();
+    for p in month_files(months) {
+        match std::fs::read_to_string(p) {
+            Ok(s) => res.extend(parse_entries(&s)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(merge_entries(res))
+}
+
+fn quoted(s: &str) -> String {
+    let mut res = "\"".to_string();
+    for c in s.chars() {
+        match c {
+            '\"' => res.push_str("\\\""),
+            '\n' => res.push_str("\\n"),
+            '\\' => res.push_str("\\\\"),
+            _ => res.push(c),
+        }
+    }
+    res.push('\"');
+    res
+}
+
+pub fn write_entries<W: Write>(w: &mut W, entries: &[Entry]) -> std::io::Result<()> {
+    let mut last: Option<&str> = None;
+    for e in entries {
+        if last != Some(&e.cmd) {
+            writeln!(w, "c{}", quoted(&e.cmd))?;
+            last = Some(&e.cmd);
+        }
+        writeln!(w, "r{},h{},p{}", e.recent, e.hits, quoted(&e.dir))?;
+    }
+    Ok(())
+}
+
+/// Write aside and rename, so a crash never leaves half a file
+fn rewrite<F: FnOnce(&mut std::fs::File) -> std::io::Result<()>>(
+    p: &Path,
+    f: F,
+) -> anyhow::Result<()> {
+    let tmp = p.with_extension("fd.tmp");
+    let mut fl = std::fs::File::create(&tmp)?;
+    f(&mut fl)?;
+    fl.sync_all()?;
+    std::fs::rename(&tmp, p)?;
+    Ok(())
+}
+
+/// Removes every command matching f from all history files.
+/// Returns the number of entries removed
+pub fn delete_matching<F: Fn(&str) -> bool>(f: F) -> anyhow::Result<usize> {
+    let mut removed = 0;
+    let _lock = HistoryLock::take()?;
+    for p in all_files()? {
+        let mut entries = parse_entries(&std::fs::read_to_string(&p)?)?;
+        let len = entries.len();
+        entries.retain(|e| !f(&e.cmd));
+        if entries.len() == len {
+            continue;
+        }
+        removed += len - entries.len();
+        entries.sort_by(|a, b| a.cmd.cmp(&b.cmd));
+        rewrite(&p, |w| write_entries(w, &entries))?;
+    }
+    //Timings could give away what was deleted
+    for p in files_starting("timing_")? {
+        let mut ts = parse_timings(&std::fs::read_to_string(&p)?);
+        let len = ts.len();
+        ts.retain(|t| !f(&t.cmd));
+        if ts.len() != len {
+            rewrite(&p, |w| write_timings(w, &ts))?;
+        }
+    }
+    if removed > 0 {
+        request_reload();
+    }
+    Ok(removed)
+}
+
+fn time_str(t: u64) -> String {
+    Local
+        .timestamp(t as i64, 0)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+fn dir_str(d: &str) -> String {
+    match std::env::var("HOME") {
+        Ok(h) if !h.is_empty() && d.starts_with(&h) => format!("~{}", &d[h.len()..]),
+        _ => d.to_string(),
+    }
+}
+
+fn print_entries<F: Fn(&Entry) -> bool>(store: &Store, f: F) -> anyhow::Result<bool> {
+    for e in read_entries(months(store))?.iter().filter(|e| f(e)) {
+        println!(
+            "{}  {:>4}  {}  {}",
+            time_str(e.recent),
+            e.hits,
+            dir_str(&e.dir),
+            e.cmd
+        );
+    }
+    Ok(true)
+}
+
+fn print_timings(store: &Store, pat: &str) -> anyhow::Result<bool> {
+    for t in read_timings(months(store))
+        .iter()
+        .filter(|t| t.cmd.contains(pat))
+    {
+        println!(
+            "{}  {:>7}  {:>7} cpu  {:>3}  {}",
+            time_str(t.when),
+            fmt_duration(t.times.wall),
+            fmt_duration(t.times.cpu()),
+            t.status,
+            t.cmd
+        );
+    }
+    Ok(true)
+}
+
+fn print_stats(store: &Store) -> anyhow::Result<bool> {
+    let entries = read_entries(months(store))?;
+    let mut cmds: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut dirs: BTreeMap<&str, usize> = BTreeMap::new();
+    for e in &entries {
+        *cmds.entry(&e.cmd).or_insert(0) += e.hits;
+        *dirs.entry(&e.dir).or_insert(0) += e.hits;
+    }
+    let runs: usize = entries.iter().map(|e| e.hits).sum();
+    println!(
+        "{} months : {} commands, {} runs, {} directories",
+        months(store),
+        cmds.len(),
+        runs,
+        dirs.len()
+    );
+    if let (Some(first), Some(last)) = (entries.first(), entries.last()) {
+        println!(
+            "From {} to {}",
+            time_str(first.recent),
+            time_str(last.recent)
+        );
+    }
+    let mut cmds: Vec<_> = cmds.into_iter().collect();
+    cmds.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+    println!("Most used commands:");
+    for (c, n) in cmds.iter().take(10) {
+        println!("{:>6}  {}", n, c);
+    }
+    let mut dirs: Vec<_> = dirs.into_iter().collect();
+    dirs.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+    println!("Most used directories:");
+    for (d, n) in dirs.iter().take(5) {
+        println!("{:>6}  {}", n, dir_str(d));
+    }
+    Ok(true)
+}
+
+/// The "history" builtin:
+/// * history [list] [pattern]
+/// * history here [pattern]
+/// * history dir <dir> [pattern]
+/// * history delete <pattern>
+/// * history merge
+/// * history scrub
+/// * history stats
+/// * history times [pattern]
+pub fn builtin(args: &[String], store: &Store) -> anyhow::Result<bool> {
+    let pat = |n: usize| args.get(n).map(String::as_str).unwrap_or("");
+    match args.first().map(String::as_str) {
+        None => print_entries(store, |_| true),
+        Some("list") => print_entries(store, |e| e.cmd.contains(pat(1))),
+        Some("here") => {
+            let here = ru_history::here();
+            print_entries(store, |e| e.dir == here && e.cmd.contains(pat(1)))
+        }
+        Some("dir") => {
+            let dir = match args.get(1) {
+                Some(d) => std::fs::canonicalize(d)?.display().to_string(),
+                None => return err_tools::e_str("history dir needs a directory"),
+            };
+            print_entries(store, |e| e.dir == dir && e.cmd.contains(pat(2)))
+        }
+        Some("delete") => {
+            if pat(1).is_empty() {
+                return err_tools::e_str("history delete needs a pattern");
+            }
+            let n = delete_matching(|c| c.contains(pat(1)))?;
+            println!("Deleted {} history entries", n);
+            Ok(true)
+        }
+        Some("merge") => {
+            //Our own commands are already on disk, so reloading picks up
+            //everything other sessions have saved since
+            request_reload();
+            Ok(true)
+        }
+        Some("scrub") => {
+            let rules = IgnoreRules::from_store(store)?;
+            let n = delete_matching(|c| rules.ignores(c))?;
+            println!("Scrubbed {} history entries", n);
+            Ok(true)
+        }
+        Some("stats") => print_stats(store),
+        Some("times") => print_timings(store, pat(1)),
+        Some(p) => print_entries(store, |e| e.cmd.contains(p)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn entries_round_trip() {
+        let entries = vec![
+            Entry {
+                cmd: "echo \"hi\"".to_string(),
+                dir: "/home".to_string(),
+                recent: 10,
+                hits: 2,
+            },
+            Entry {
+                cmd: "echo \"hi\"".to_string(),
+                dir: "/park".to_string(),
+                recent: 12,
+                hits: 1,
+            },
+            Entry {
+                cmd: "ls\nls".to_string(),
+                dir: "/home".to_string(),
+                recent: 11,
+                hits: 4,
+            },
+        ];
+        let mut v: Vec<u8> = Vec::new();
+        write_entries(&mut v, &entries).unwrap();
+        let s = String::from_utf8(v).unwrap();
+        assert_eq!(parse_entries(&s).unwrap(), entries);
+
+        let mut hs = HistoryStore::new();
+        ru_history::parse::parse_onto(&mut hs, &s).unwrap();
+        assert_eq!(hs.complete("ls", "/home", 2), ["ls\nls"]);
+    }
+
+    #[test]
+    fn timings_round_trip() {
+        let ms = Duration::from_millis;
+        let t = Timing {
+            cmd: "echo \"a,cb\"\nls".to_string(),
+            when: 100,
+            times: Times {
+                wall: ms(1500),
+                user: ms(20),
+                sys: ms(3),
+            },
+            status: 2,
+        };
+        let mut v: Vec<u8> = Vec::new();
+        write_timings(&mut v, &[t.clone(), t.clone()]).unwrap();
+        let s = String::from_utf8(v).unwrap() + "junk\n";
+        assert_eq!(parse_timings(&s), vec![t.clone(), t]);
+        assert_eq!(
+            timing_file(Path::new("/h/history_2020_3.fd")),
+            Path::new("/h/timing_2020_3.fd")
+        );
+    }
+
+    #[test]
+    fn ignore_rules() {
+        let store = Store::new();
+        store.let_set(
+            "RU_HISTORY_MAX_LEN".to_string(),
+            Data::Str("12".to_string()),
+        );
+        store.let_set(
+            "RU_HISTORY_IGNORE".to_string(),
+            Data::List(vec![Data::Str("TOKEN=".to_string())]),
+        );
+        let rules = IgnoreRules::from_store(&store).unwrap();
+        assert!(rules.ignores(" ls"));
+        assert!(rules.ignores("export TOKEN=a"));
+        assert!(rules.ignores("echo 1234567890"));
+        assert!(!rules.ignores("ls -l"));
+        assert!(rules.dedupe);
+    }
+
+    #[test]
+    fn merge_sums_hits() {
+        let e = |recent, hits| Entry {
+            cmd: "ls".to_string(),
+            dir: "/".to_string(),
+            recent,
+            hits,
+        };
+        assert_eq!(merge_entries(vec![e(3, 1), e(5, 2)]), vec![e(5, 3)]);
+    }
+}
@@ -0,0 +1,122 @@
+//! Job control, for the interactive shell.
+//!
+//! Each command run in the foreground gets its own process group, with every program
+//! in its pipe, and that group is given the terminal while it runs. So Ctrl-C, Ctrl-Z
+//! and Ctrl-\ go to it and not the shell, and a stopped job can carry on with "fg".
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ON: AtomicBool = AtomicBool::new(false);
+
+/// Turns on job control. The shell ignores SIGTTOU, so it can take the terminal back
+pub fn enable() {
+    unsafe { libc::signal(libc::SIGTTOU, libc::SIG_IGN) };
+    ON.store(true, Ordering::SeqCst);
+}
+
+#[derive(Debug, Default)]
+pub struct Job {
+    on: bool,
+    /// The group, once the first program is started
+    pgid: Option<u32>,
+    /// Every program started, to be waited on
+    pids: Vec<u32>,
+}
+
+impl Job {
+    /// Not a job, the programs stay in the shell's group
+    pub fn none() -> Self {
+        Job::default()
+    }
+
+    /// A foreground job, if job control is on
+    pub fn foreground() -> Self {
+        Job {
+            on: ON.load(Ordering::SeqCst),
+            ..Job::default()
+        }
+    }
+
+    /// Sets cmd to join the job's group and take the terminal
+    pub fn setup(&self, cmd: &mut Command) {
+        if !self.on {
+            //Ignored signals stay ignored through exec, so "$(..)", "disown" and
+            //prompt commands get SIGTTOU back like any background program
+            if ON.load(Ordering::SeqCst) {
+                unsafe {
+                    cmd.pre_exec(|| {
+                        libc::signal(libc::SIGTTOU, libc::SIG_DFL);
+                        Ok(())
+                    });
+                }
+            }
+            return;
+        }
+        cmd.process_group(self.pgid.unwrap_or(0) as i32);
+        unsafe {
+            cmd.pre_exec(|| {
+                //The child is not in the foreground yet, so SIGTTOU is blocked to take it
+                let mut set: libc::sigset_t = std::mem::zeroed();
+                let mut old: libc::sigset_t = std::mem::zeroed();
+                libc::sigemptyset(&mut set);
+                libc::sigaddset(&mut set, libc::SIGTTOU);
+                libc::sigprocmask(libc::SIG_BLOCK, &set, &mut old);
+                libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+                libc::sigprocmask(libc::SIG_SETMASK, &old, std::ptr::null_mut());
+                libc::signal(libc::SIGTTOU, libc::SIG_DFL);
+                Ok(())
+            });
+        }
+    }
+
+    pub fn pids(&self) -> &[u32] {
+        &self.pids
+    }
+
+    /// Records a started program. The first one's pid names the group
+    pub fn started(&mut self, pid: u32) {
+        self.pids.push(pid);
+        if !self.on || self.pgid.is_some() {
+            return;
+        }
+        self.pgid = Some(pid);
+        //Also done here, in case the shell waits before the child gets to it
+        give_terminal(pid);
+    }
+
+    /// Gives the terminal back to the shell
+    pub fn done(&self) {
+        if self.pgid.is_some() {
+            take_terminal();
+        }
+    }
+}
+
+fn give_terminal(pgid: u32) {
+    unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, pgid as libc::pid_t) };
+}
+
+pub fn take_terminal() {
+    unsafe { libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp()) };
+}
+
+/// Carries on a stopped job in the foreground, returning once it stops or ends
+pub fn resume(pids: &[u32]) -> std::io::Result<std::process::ExitStatus> {
+    let first = pids.first().copied().unwrap_or(0) as libc::pid_t;
+    let pgid = unsafe { libc::getpgid(first) };
+    let on = ON.load(Ordering::SeqCst) && pgid > 0 && pgid != unsafe { libc::getpgrp() };
+    if on {
+        give_terminal(pgid as u32);
+        unsafe { libc::kill(-pgid, libc::SIGCONT) };
+    } else {
+        for &pid in pids {
+            unsafe { libc::kill(pid as libc::pid_t, libc::SIGCONT) };
+        }
+    }
+    let res = crate::timing::wait_pids(pids);
+    if on {
+        take_terminal();
+    }
+    res
+}
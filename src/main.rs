@@ -1,29 +1,45 @@
+mod alias;
 mod args;
+mod brackets;
 mod channel;
+mod comp_spec;
+mod computed;
 mod cursor;
 mod data;
+mod diagnose;
+mod events;
 mod exec;
 mod expr;
+mod fish;
+mod fuzzy;
 mod guess_manager;
 mod highlight;
+mod history;
+mod job;
+mod menu;
+mod not_found;
 mod parser;
 mod partial;
+mod path_index;
 mod prompt;
+mod prompt_cmd;
+mod render;
+mod segments;
 mod shell;
+mod signals;
 mod statement;
 mod store;
 mod str_util;
 mod tab_complete;
+mod timing;
 mod ui;
 
-use bogobble::traits::*;
 use clap::*;
 use err_tools::*;
 use shell::Shell;
 use std::io::*;
 use store::Store;
 use termion::event::Event;
-use termion::input::TermReadEventsAndRaw;
 use termion::raw::{IntoRawMode, RawTerminal};
 
 type RT = RawTerminal<Stdout>;
@@ -69,12 +85,12 @@ fn shell_main(clp: &clap::ArgMatches) -> anyhow::Result<()> {
 
     match termion::is_tty(&stdin()) {
         true => run_interactive(),
-        false => run_stream_out(&mut stdin(), &mut Store::new()).map(|_| ()),
+        false => run_stream_out(&mut stdin(), "<stdin>", &mut Store::new()).map(|_| ()),
     }
 }
 
 pub fn run_interactive() -> anyhow::Result<()> {
-    ctrlc::set_handler(move || println!("Kill Signal")).ok();
+    signals::install();
     let mut shell = Shell::new();
     let mut rt = stdout().into_raw_mode()?;
 
@@ -84,15 +100,21 @@ pub fn run_interactive() -> anyhow::Result<()> {
     if let Err(e) = shell.store.source_path(init) {
         println!("Error sourcing home_config : {}", e);
     }
+    shell.load_history();
+    shell.re_highlight();
 
     shell.reset(&mut rt);
 
-    for raw_e in stdin().events_and_raw() {
-        let e = match raw_e {
-            Ok((e, _)) => e,
-            Err(e) => {
+    for m in events::Events::start() {
+        let e = match m {
+            events::Msg::Input(Ok(e)) => e,
+            events::Msg::Input(Err(e)) => {
                 return e_string(format!("Input Error {}", e));
             }
+            events::Msg::Redraw | events::Msg::Resize => {
+                shell.redraw_prompt(&mut rt);
+                continue;
+            }
         };
         match do_event(e, &mut shell, &mut rt) {
             Ok(Action::Quit) => {
@@ -107,13 +129,19 @@ pub fn run_interactive() -> anyhow::Result<()> {
 }
 
 pub fn run_file<P: AsRef<std::path::Path>>(fname: P, store: &mut Store) -> anyhow::Result<bool> {
+    let name = fname.as_ref().display().to_string();
     let mut f = std::fs::File::open(fname)?;
-    run_stream_out(&mut f, store)
+    run_stream_out(&mut f, &name, store)
 }
 
-pub fn run_stream_out<T: std::io::Read>(t: &mut T, store: &mut Store) -> anyhow::Result<bool> {
+/// Runs everything read from t, name says where it came from in errors
+pub fn run_stream_out<T: std::io::Read>(
+    t: &mut T,
+    name: &str,
+    store: &mut Store,
+) -> anyhow::Result<bool> {
     let mut s = String::new();
     t.read_to_string(&mut s)?;
-    let ar = parser::Lines.parse_s(&s).map_err(|e| e.strung())?;
+    let ar = diagnose::parse_lines(&s, name)?;
     crate::statement::run_block(&ar, &mut store.child())
 }
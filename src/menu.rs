@@ -0,0 +1,205 @@
+//! The completion menu shown under the prompt while choosing an option
+use crate::fuzzy::fuzzy_match;
+use std::fmt::Write;
+use termion::{color, style};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate {
+    pub text: String,
+    pub desc: Option<String>,
+    /// Byte indices in text of chars matching what was typed
+    pub matched: Vec<usize>,
+}
+
+impl Candidate {
+    pub fn new<S: Into<String>>(text: S) -> Self {
+        Candidate {
+            text: text.into(),
+            desc: None,
+            matched: Vec::new(),
+        }
+    }
+
+    pub fn with_desc<S: Into<String>, D: Into<String>>(text: S, desc: D) -> Self {
+        Candidate {
+            text: text.into(),
+            desc: Some(desc.into()),
+            matched: Vec::new(),
+        }
+    }
+
+    /// Paths only show the last part of the name, the rest is typed already
+    pub fn label(&self) -> &str {
+        let t = self.text.trim_end_matches('/');
+        match t.rfind('/') {
+            Some(n) => &self.text[n + 1..],
+            None => &self.text,
+        }
+    }
+}
+
+impl From<String> for Candidate {
+    fn from(s: String) -> Self {
+        Candidate::new(s)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Menu {
+    /// Where the word being completed starts in the buffer
+    pub start: usize,
+    /// How much of the word was after the cursor when the menu opened
+    pub tail: usize,
+    all: Vec<Candidate>,
+    shown: Vec<usize>,
+    selected: Option<usize>,
+}
+
+impl Menu {
+    pub fn new(start: usize, tail: usize, all: Vec<Candidate>) -> Self {
+        let shown = (0..all.len()).collect();
+        Menu {
+            start,
+            tail,
+            all,
+            shown,
+            selected: None,
+        }
+    }
+
+    /// Keeps only candidates fuzzy matching word, best first.
+    /// Returns false if nothing is left to show
+    pub fn filter(&mut self, word: &str) -> bool {
+        let mut scores = Vec::new();
+        for (i, c) in self.all.iter_mut().enumerate() {
+            if let Some(m) = fuzzy_match(word, &c.text) {
+                c.matched = m.indices;
+                scores.push((m.score, i));
+            }
+        }
+        scores.sort_by_key(|(sc, _)| std::cmp::Reverse(*sc));
+        self.shown = scores.into_iter().map(|(_, i)| i).collect();
+        self.selected = None;
+        !self.shown.is_empty()
+    }
+
+    pub fn next(&mut self) {
+        self.selected = match self.selected {
+            Some(n) if n + 1 < self.shown.len() => Some(n + 1),
+            _ => Some(0),
+        }
+    }
+
+    pub fn prev(&mut self) {
+        self.selected = match self.selected {
+            Some(n) if n > 0 => Some(n - 1),
+            _ => Some(self.shown.len().saturating_sub(1)),
+        }
+    }
+
+    pub fn selected(&self) -> Option<&Candidate> {
+        self.selected
+            .and_then(|n| self.shown.get(n))
+            .map(|i| &self.all[*i])
+    }
+
+    /// Lays the candidates out in as many columns as fit in width,
+    /// scrolling to keep the selection within max_rows
+    pub fn build(&self, width: usize, max_rows: usize) -> String {
+        let mut res = String::new();
+        let cell_w = self
+            .shown
+            .iter()
+            .map(|i| cell_width(&self.all[*i]))
+            .max()
+            .unwrap_or(0)
+            .min(width);
+        let cols = ((width + 2) / (cell_w + 2)).max(1);
+        let rows = self.shown.len().div_ceil(cols);
+        let sel_row = self.selected.map(|n| n / cols).unwrap_or(0);
+        let top = (sel_row + 1).saturating_sub(max_rows);
+
+        for r in top..rows.min(top + max_rows) {
+            res.push('\n');
+            for c in 0..cols {
+                let n = r * cols + c;
+                let cand = match self.shown.get(n) {
+                    Some(i) => &self.all[*i],
+                    None => break,
+                };
+                if c > 0 {
+                    res.push_str("  ");
+                }
+                if self.selected == Some(n) {
+                    write!(res, "{}", style::Invert).ok();
+                }
+                write_label(&mut res, cand);
+                if self.selected == Some(n) {
+                    write!(res, "{}", style::NoInvert).ok();
+                }
+                if let Some(d) = &cand.desc {
+                    write!(
+                        res,
+                        "  {}{}{}",
+                        color::Fg(color::LightBlack),
+                        d,
+                        color::Fg(color::Reset)
+                    )
+                    .ok();
+                }
+                if c + 1 < cols && n + 1 < self.shown.len() {
+                    let pad = cell_w.saturating_sub(cell_width(cand));
+                    res.extend(std::iter::repeat_n(' ', pad));
+                }
+            }
+        }
+        if rows > max_rows {
+            let pos = self.selected.map(|n| n + 1).unwrap_or(0);
+            write!(res, "\n[{}/{}]", pos, self.shown.len()).ok();
+        }
+        res
+    }
+}
+
+/// Writes the label, underlining the chars that were matched
+fn write_label(res: &mut String, c: &Candidate) {
+    let label = c.label();
+    let off = c.text.len() - label.len();
+    for (i, ch) in label.char_indices() {
+        match c.matched.contains(&(off + i)) {
+            true => write!(res, "{}{}{}", style::Underline, ch, style::NoUnderline),
+            false => write!(res, "{}", ch),
+        }
+        .ok();
+    }
+}
+
+fn cell_width(c: &Candidate) -> usize {
+    let lw = console::measure_text_width(c.label());
+    match &c.desc {
+        Some(d) => lw + 2 + console::measure_text_width(d),
+        None => lw,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn filter_and_cycle() {
+        let v = ["src/", "src/main.rs", "readme.md"]
+            .iter()
+            .map(|s| Candidate::new(*s))
+            .collect();
+        let mut m = Menu::new(0, 0, v);
+        assert!(m.filter("src"));
+        m.next();
+        m.next();
+        assert_eq!(m.selected().map(Candidate::label), Some("main.rs"));
+        m.next();
+        assert_eq!(m.selected().map(Candidate::label), Some("src/"));
+        m.prev();
+        assert_eq!(m.selected().map(Candidate::label), Some("main.rs"));
+        assert!(!m.filter("x"));
+    }
+}
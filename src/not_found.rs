@@ -0,0 +1,109 @@
+//! Says why a program could not be started, and what may have been meant instead.
+//!
+//! If "RU_NOT_FOUND" holds a command, it is run when a command is missing,
+//! with "$RU_CMD" set to the name and "$RU_ARGS" to the args given.
+use crate::data::Data;
+use crate::parser;
+use crate::path_index;
+use crate::store::Store;
+use crate::str_util::edit_distance;
+use bogobble::traits::*;
+use err_tools::*;
+use std::io::ErrorKind;
+use std::process::Stdio;
+
+/// Close names from programs, builtins and aliases, closest first
+pub fn suggestions(cmd: &str, store: &Store) -> Vec<String> {
+    let mut names = path_index::programs(store);
+    names.extend(parser::BUILTINS.iter().map(|b| b.to_string()));
+    store.do_with("RU_ALIAS", |d| {
+        if let Data::Map(m) = d {
+            names.extend(m.keys().cloned());
+        }
+    });
+    let len = cmd.chars().count();
+    let max = (len / 3).max(1);
+    let mut v: Vec<(usize, String)> = names
+        .into_iter()
+        .filter(|n| n != cmd && n.chars().count().abs_diff(len) <= max)
+        .map(|n| (edit_distance(cmd, &n), n))
+        .filter(|(d, _)| *d <= max)
+        .collect();
+    v.sort();
+    v.into_iter().take(3).map(|(_, n)| n).collect()
+}
+
+fn run_hook(hook: &str, cmd: &str, args: &[String], store: &Store) -> anyhow::Result<()> {
+    let ex = parser::PExec.parse_s(hook).map_err(|e| e.strung())?;
+    let mut ch_store = store.child();
+    //A missing hook must not call itself
+    ch_store.let_set("RU_NOT_FOUND".to_string(), Data::Str(String::new()));
+    ch_store.let_set("RU_CMD".to_string(), Data::Str(cmd.to_string()));
+    let args = args.iter().map(|a| Data::Str(a.clone())).collect();
+    ch_store.let_set("RU_ARGS".to_string(), Data::List(args));
+    ex.run(
+        &mut ch_store,
+        Stdio::inherit(),
+        Stdio::inherit(),
+        Stdio::inherit(),
+    )?
+    .wait()?;
+    Ok(())
+}
+
+fn not_found(cmd: &str, args: &[String], store: &Store) -> anyhow::Error {
+    if cmd.contains('/') {
+        return SgError(format!("No such file : {}", cmd)).into();
+    }
+    let hook = store.get("RU_NOT_FOUND").map(|h| h.to_string());
+    if let Some(h) = hook.filter(|h| !h.trim().is_empty()) {
+        return match run_hook(&h, cmd, args, store) {
+            Ok(()) => SgError(format!("Command not found : {}", cmd)).into(),
+            Err(e) => SgError(format!(
+                "Command not found : {} (RU_NOT_FOUND : {})",
+                cmd, e
+            ))
+            .into(),
+        };
+    }
+    match suggestions(cmd, store).as_slice() {
+        [] => SgError(format!("Command not found : {}", cmd)).into(),
+        v => SgError(format!(
+            "Command not found : {}, did you mean : {}",
+            cmd,
+            v.join(", ")
+        ))
+        .into(),
+    }
+}
+
+/// Turns the error from spawning cmd into one saying what went wrong
+pub fn spawn_error(e: std::io::Error, cmd: &str, args: &[String], store: &Store) -> anyhow::Error {
+    match e.kind() {
+        ErrorKind::NotFound => not_found(cmd, args, store),
+        ErrorKind::PermissionDenied => SgError(format!("Permission denied : {}", cmd)).into(),
+        _ => SgError(format!("Error running {} : {}", cmd, e)).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn close_names() {
+        let store = Store::new();
+        store.let_set("PATH".to_string(), Data::Str(String::new()));
+        let mut al = std::collections::BTreeMap::new();
+        al.insert("gst".to_string(), Data::Str("git status".to_string()));
+        store.let_set("RU_ALIAS".to_string(), Data::Map(al));
+        assert_eq!(suggestions("dc", &store), ["cd"]);
+        assert_eq!(suggestions("gts", &store), ["gst"]);
+        assert!(suggestions("zzzz", &store).is_empty());
+        assert!(!suggestions("cd", &store).contains(&"cd".to_string()));
+        let e = spawn_error(ErrorKind::NotFound.into(), "lod", &[], &store);
+        assert_eq!(
+            e.to_string(),
+            "Command not found : lod, did you mean : load"
+        );
+    }
+}
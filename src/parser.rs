@@ -33,8 +33,24 @@ parser! {(Path->String)
     string((maybe("~"),plus(or_ig!("\\ ",("/.",LetterNum).iplus()))))
 }
 
+/// Keep these in step with the Builtin, Assigner and Statement parsers below,
+/// they are used for completion.
+pub const BUILTINS: &[&str] = &[
+    "cd",
+    "load",
+    "proglist",
+    "var",
+    "scope_depth",
+    "history",
+    "alias",
+    "unalias",
+    "fg",
+];
+pub const ASSIGNERS: &[&str] = &["let", "set", "export", "push"];
+pub const KEYWORDS: &[&str] = &["for", "if", "else", "disown", "time"];
+
 parser! {(Builtin->&'static str)
-    or!("cd","load","proglist","var","scope_depth")
+    or!("cd","load","proglist","var","scope_depth","history","alias","unalias","fg")
 }
 
 parser! {(Assigner->&'static str)
@@ -97,14 +113,16 @@ parser! {(ExprLeft ->Expr)
 
 }
 parser! {(ExprRight -> Expr)
-    (ExprLeft,maybe((ws_(or("&&","||")),wn_(ExprRight)))).map(|(lt,op)|{
-        match op {
-            Some(("&&",rt))=>Expr::And(Box::new(lt),Box::new(rt)),
-            Some(("||",rt))=>Expr::Or(Box::new(lt),Box::new(rt)),
-            _=>lt,
-        }
-
-    })
+    or!(
+        (keyword("time"),ws_(ExprRight)).map(|(_,e)|Expr::Time(Box::new(e))),
+        (ExprLeft,maybe((ws_(or("&&","||")),wn_(ExprRight)))).map(|(lt,op)|{
+            match op {
+                Some(("&&",rt))=>Expr::And(Box::new(lt),Box::new(rt)),
+                Some(("||",rt))=>Expr::Or(Box::new(lt),Box::new(rt)),
+                _=>lt,
+            }
+        }),
+    )
 }
 
 parser! {(ExTarget->Exec)
@@ -150,7 +150,7 @@ ss_parser! {FullStatement:ParseMark,
 }
 
 ss_parser! { Builtin:ParseMark,
-    ss_or!(kw("cd"),kw("load"),kw("proglist"),kw("var"),kw("scope_depth"))
+    ss_or!(kw("cd"),kw("load"),kw("proglist"),kw("var"),kw("scope_depth"),kw("history"),kw("alias"),kw("unalias"),kw("fg"))
 }
 
 ss_parser! { Assigner:ParseMark,
@@ -179,7 +179,10 @@ ss_parser! {ExprLeft:ParseMark,
 }
 
 ss_parser! {ExprRight:ParseMark,
-    pl!(ExprLeft,Maybe((Ws,Item::Symbol,(ss_or!("&&","||"),Item::Close),(Wn,ExprRight))))
+    ss_or!(
+        pl!(kw("time"),ExprRight),
+        pl!(ExprLeft,Maybe((Ws,Item::Symbol,(ss_or!("&&","||"),Item::Close),(Wn,ExprRight))))
+    )
 }
 
 ss_parser! {ExTarget:ParseMark,
@@ -0,0 +1,126 @@
+//! Index of the programs on PATH, so completing a command doesn't read every folder.
+//!
+//! Each folder is only read again when its modified time changes, and the folders
+//! kept follow PATH as it is in the Store. A chmod doesn't change the folder, so
+//! whether each file can be run is checked again every time.
+use crate::store::Store;
+use std::collections::{BTreeMap, BTreeSet};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+#[derive(Debug)]
+struct Folder {
+    mtime: SystemTime,
+    files: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    path: String,
+    folders: BTreeMap<PathBuf, Folder>,
+}
+
+static INDEX: Mutex<Option<Index>> = Mutex::new(None);
+
+/// A file anyone may execute, following links
+pub fn is_executable(p: &Path) -> bool {
+    match std::fs::metadata(p) {
+        Ok(m) => m.is_file() && m.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+fn read_folder(p: &Path) -> Vec<String> {
+    let entries = match std::fs::read_dir(p) {
+        Ok(e) => e,
+        Err(_) => return Vec::new(),
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .collect()
+}
+
+impl Index {
+    fn refresh(&mut self, path: &str) {
+        if path != self.path {
+            let dirs: BTreeSet<&str> = path.split(':').collect();
+            self.folders
+                .retain(|k, _| dirs.contains(k.to_string_lossy().as_ref()));
+            self.path = path.to_string();
+        }
+        for d in self.path.split(':').filter(|d| !d.is_empty()) {
+            let mtime = match std::fs::metadata(d).and_then(|m| m.modified()) {
+                Ok(t) => t,
+                Err(_) => {
+                    self.folders.remove(Path::new(d));
+                    continue;
+                }
+            };
+            match self.folders.get(Path::new(d)) {
+                Some(f) if f.mtime == mtime => {}
+                _ => {
+                    let files = read_folder(Path::new(d));
+                    self.folders
+                        .insert(PathBuf::from(d), Folder { mtime, files });
+                }
+            }
+        }
+    }
+}
+
+/// Every executable on the PATH held by the store
+pub fn programs(store: &Store) -> BTreeSet<String> {
+    let path = store.get("PATH").map(|p| p.to_string()).unwrap_or_default();
+    let mut lock = match INDEX.lock() {
+        Ok(l) => l,
+        Err(p) => p.into_inner(),
+    };
+    let index = lock.get_or_insert_with(Index::default);
+    index.refresh(&path);
+    index
+        .folders
+        .iter()
+        .flat_map(|(d, f)| f.files.iter().filter(move |n| is_executable(&d.join(n))))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Data;
+    use std::fs;
+
+    #[test]
+    fn only_executables() {
+        let dir = std::env::temp_dir().join(format!("ru_path_index_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let make = |name: &str, mode: u32| {
+            let p = dir.join(name);
+            fs::write(&p, "").unwrap();
+            fs::set_permissions(&p, fs::Permissions::from_mode(mode)).unwrap();
+        };
+        make("ru_prog_a", 0o755);
+        make("ru_notes", 0o644);
+        make("ru_prog_b", 0o600);
+        let store = Store::new();
+        store.let_set(
+            "PATH".to_string(),
+            Data::Str(dir.to_string_lossy().to_string()),
+        );
+
+        let v: Vec<String> = programs(&store).into_iter().collect();
+        assert_eq!(v, ["ru_prog_a"]);
+        //The folder is not changed by chmod
+        let b = dir.join("ru_prog_b");
+        fs::set_permissions(&b, fs::Permissions::from_mode(0o700)).unwrap();
+        assert!(programs(&store).contains("ru_prog_b"));
+
+        store.let_set("PATH".to_string(), Data::Str(String::new()));
+        assert!(programs(&store).is_empty());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
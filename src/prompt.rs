@@ -1,22 +1,46 @@
 //Manages carring all the messages to the user
+use crate::brackets;
 use crate::cursor::Cursor;
+use crate::diagnose::Diagnosis;
 use crate::guess_manager::*;
 use crate::highlight::Highlight;
+use crate::menu::Menu;
+use crate::partial::Item;
+use crate::render::Screen;
+use crate::str_util;
 use crate::ui;
 use crate::RT;
-use bogobble::partial::ranger::Ranger;
+use std::collections::BTreeMap;
 use std::fmt::Write;
 use std::io::Write as IWrite;
-use std::path::PathBuf;
 use termion::color;
+use termion::event::Key;
 
 #[derive(Debug, Clone)]
 pub struct Prompt {
-    pr_line: String,
-    built: String,
+    pub pr_line: String,
+    screen: Screen,
     restore: Option<Cursor>,
-    pub options: Option<(Ranger, Vec<String>)>,
+    pub menu: Option<Menu>,
     pub message: Option<String>,
+    /// The rest of a history entry, drawn greyed out after the line
+    pub suggestion: Option<String>,
+    /// What the aliases in the line expand to, drawn under it
+    pub preview: Option<String>,
+    /// Abbreviations from RU_ABBR, expanded in command position on space
+    pub abbrs: BTreeMap<String, String>,
+    /// The brackets and quotes from RU_AUTOPAIR, closed as soon as they are typed
+    pub pairs: String,
+    /// Added for each block a new line is inside, from RU_INDENT
+    pub indent: String,
+    /// Why the line did not parse, shown by a caret under the place
+    pub error: Option<Diagnosis>,
+    /// Mark the bracket matching the one at the cursor
+    pub show_match: bool,
+    /// Drawn at the right of the first line, from RU_RPROMPT
+    pub rprompt: String,
+    /// What the prompt becomes once its line is run, from RU_TRANSIENT_PROMPT
+    pub transient: Option<String>,
     pub cursor: Cursor,
     pub guess_man: GuessManager,
     pub highlight: Highlight,
@@ -26,18 +50,27 @@ impl Prompt {
     pub fn new(pr_line: String) -> Self {
         Prompt {
             pr_line,
-            options: None,
+            menu: None,
             message: None,
+            suggestion: None,
+            preview: None,
+            abbrs: BTreeMap::new(),
+            pairs: String::new(),
+            indent: String::new(),
+            error: None,
+            show_match: true,
+            rprompt: String::new(),
+            transient: None,
             restore: None,
-            built: String::new(),
+            screen: Screen::default(),
             cursor: Cursor::at_end(String::new()),
             guess_man: GuessManager::new(Some(20)),
             highlight: Highlight::empty(),
         }
     }
 
-    pub fn set_highlight(&mut self, s: &str) {
-        match Highlight::from_str(s) {
+    pub fn set_highlight(&mut self, h: anyhow::Result<Highlight>) {
+        match h {
             Ok(v) => self.highlight = v,
             Err(e) => self.message = Some(format!("highlight parse error: {}", e)),
         }
@@ -45,10 +78,13 @@ impl Prompt {
 
     pub fn reset(&mut self, pr_line: String, rt: &mut RT) {
         self.pr_line = pr_line;
-        self.options = None;
+        self.menu = None;
         self.message = None;
+        self.suggestion = None;
+        self.preview = None;
+        self.error = None;
         self.restore = None;
-        self.built = String::new();
+        self.screen.forget();
         self.cursor = Cursor::at_end(String::new());
         self.guess_man.clear();
         self.print(rt);
@@ -57,19 +93,13 @@ impl Prompt {
     pub fn esc(&mut self, rt: &mut RT) {
         self.unprint(rt);
         self.clear_help();
+        self.error = None;
+        self.suggestion = None;
         self.restore = None;
         self.guess_man.clear();
         self.print(rt);
     }
 
-    pub fn set_guesses(&mut self, v: Vec<String>) {
-        let go_up = v.len() > 0;
-        self.guess_man.set_guesses(v);
-        if go_up {
-            self.up();
-        }
-    }
-
     pub fn up(&mut self) {
         match self.guess_man.next() {
             Some(s) => self.replace_cursor(s),
@@ -91,7 +121,13 @@ impl Prompt {
         }
     }
 
+    /// Puts a history entry in the line, indented as if typed here
     pub fn replace_cursor(&mut self, s: String) {
+        let s = match self.indent.is_empty() {
+            true => s,
+            false => brackets::reindent(&s, &self.indent),
+        };
+        self.error = None;
         let mut new_cursor = Cursor::at_end(s);
         std::mem::swap(&mut self.cursor, &mut new_cursor);
         if let None = &self.restore {
@@ -118,37 +154,73 @@ impl Prompt {
     }
     */
 
+    /// Accepts the whole suggestion, or just its next word
+    /// Returns false if there was nothing to accept
+    pub fn accept_suggestion(&mut self, one_word: bool) -> bool {
+        let sg = match (&self.suggestion, self.cursor.is_end()) {
+            (Some(sg), true) => sg.clone(),
+            _ => return false,
+        };
+        let n = match one_word {
+            true => str_util::next_word_end(&sg),
+            false => sg.len(),
+        };
+        let l = self.cursor.s.len();
+        self.cursor.replace_range(l..l, &sg[..n]);
+        true
+    }
+
     pub fn clear_help(&mut self) {
-        self.options = None;
+        self.menu = None;
         self.message = None;
     }
 
     pub fn print(&mut self, rt: &mut RT) {
-        let pass1 = self.build(&self.cursor.s, true);
-        self.built = self.build(self.cursor.on_s(), false);
-        ui::print(&pass1);
-        ui::unprint(&pass1, rt, false);
-        ui::print(&self.built);
+        let full = self.build(&self.cursor.s, true);
+        let upto = self.build(self.cursor.on_s(), false);
+        self.screen.draw(full, upto);
         rt.flush().ok();
     }
 
+    /// Prints the whole line, with the cursor at the end, to be left on screen
     pub fn print_end(&mut self, rt: &mut RT) {
-        self.built = self.build(&self.cursor.s, false);
-        ui::print(&self.built);
+        ui::print(&self.build(&self.cursor.s, false));
+        self.screen.forget();
         rt.flush().ok();
     }
 
-    pub fn unprint(&self, rt: &mut RT) {
-        ui::unprint(&self.built, rt, true);
+    pub fn unprint(&self, _rt: &mut RT) {
+        self.screen.clear();
     }
 
     pub fn build(&self, line: &str, with_ops: bool) -> String {
         let mut res = String::new();
-        let (pwidth, _) = termion::terminal_size().unwrap_or((50, 50));
+        let (pwidth, pheight) = termion::terminal_size().unwrap_or((50, 50));
 
         //println!("origin = {:?}\r\n", line);
 
-        let line = build_line(line, &self.highlight);
+        let mark = match self.show_match {
+            true => brackets::near_match(&self.cursor.s, self.cursor.get_index()),
+            false => None,
+        };
+        let mut painted = build_line(line, &self.highlight, mark.filter(|n| *n < line.len()));
+        let show_sg = with_ops && self.cursor.is_end() && self.error.is_none();
+        if let (Some(sg), true) = (&self.suggestion, show_sg) {
+            write!(
+                painted,
+                "{}{}{}",
+                color::Fg(color::LightBlack),
+                sg.replace("\n", "\n... "),
+                color::Fg(color::Reset)
+            )
+            .ok();
+        }
+        if with_ops && !self.rprompt.is_empty() {
+            self.add_rprompt(&mut painted, pwidth as usize);
+        }
+        if let Some(d) = &self.error {
+            self.add_caret(d, line, &mut painted, with_ops);
+        }
 
         //println!("result = {:?}\r\n\n\n\n\n", line);
 
@@ -156,56 +228,301 @@ impl Prompt {
             write!(res, "[{}]\n\r", m).ok();
         }
         res.push_str(&self.pr_line);
-        write!(res, "{}", line).ok();
-        if let (Some((_, ops)), true) = (&self.options, with_ops) {
-            match ops.len() {
-                n if n <= 10 => {
-                    for (n, o) in ops.iter().enumerate() {
-                        write!(res, "\n{}:  {}", n, o).ok();
-                    }
-                }
-                _ => {
-                    for (n, o) in ops.iter().enumerate() {
-                        let s = match PathBuf::from(o).file_name() {
-                            Some(s) => s.to_string_lossy().to_string(),
-                            None => o.to_string(),
-                        };
-                        let nl = match n % (pwidth as usize / 25) {
-                            0 => "\n",
-                            _ => "",
-                        };
-                        write!(res, "{}{:0>2}:  {:20}", nl, n, s,).ok();
-                    }
-                }
-            }
+        res.push_str(&painted);
+        if let (Some(p), true) = (&self.preview, with_ops) {
+            write!(
+                res,
+                "\n{}alias : {}{}",
+                color::Fg(color::LightBlack),
+                p,
+                color::Fg(color::Reset)
+            )
+            .ok();
+        }
+        if let (Some(m), true) = (&self.menu, with_ops) {
+            res.push_str(&m.build(pwidth as usize, (pheight as usize / 2).max(3)));
         }
         res
     }
+
+    /// Puts the right prompt at the end of the first line, if there is room
+    fn add_rprompt(&self, painted: &mut String, width: usize) {
+        let end = painted.find('\n').unwrap_or(painted.len());
+        let pr_last = self.pr_line.rsplit('\n').next().unwrap_or("");
+        let used = ui::text_width(pr_last) + ui::text_width(&painted[..end]);
+        let rw = ui::text_width(&self.rprompt);
+        //The last column is left, so the line never wraps
+        if used + rw < width {
+            let pad = " ".repeat(width - 1 - used - rw);
+            painted.insert_str(end, &format!("{}{}", pad, self.rprompt));
+        }
+    }
+
+    /// Shows the line with the transient prompt in place of the prompt,
+    /// and without anything under it, to leave above the command's output
+    pub fn collapse(&mut self, rt: &mut RT) {
+        self.unprint(rt);
+        let full = match &self.transient {
+            Some(t) => std::mem::replace(&mut self.pr_line, t.clone()),
+            None => self.pr_line.clone(),
+        };
+        self.clear_help();
+        self.error = None;
+        self.preview = None;
+        self.print_end(rt);
+        self.pr_line = full;
+    }
+
+    /// Puts a line with a caret and the message under the line with the error,
+    /// if that line is all there in line
+    fn add_caret(&self, d: &Diagnosis, line: &str, painted: &mut String, with_ops: bool) {
+        let (l, _) = d.line_col(&self.cursor.s);
+        let indent = match l {
+            1 => ui::text_width(self.pr_line.rsplit('\n').next().unwrap_or("")),
+            _ => 4,
+        };
+        //By display width, as chars before it may be wide
+        let before = &self.cursor.s[..d.index.min(self.cursor.s.len())];
+        let col = ui::text_width(before.rsplit('\n').next().unwrap_or(""));
+        let caret = format!(
+            "\n{}{}^ {}{}",
+            " ".repeat(indent + col),
+            color::Fg(color::LightRed),
+            d.msg,
+            color::Fg(color::Reset)
+        );
+        let lines = line.matches('\n').count();
+        if lines >= l {
+            if let Some((n, _)) = painted.match_indices('\n').nth(l - 1) {
+                painted.insert_str(n, &caret);
+            }
+        } else if lines + 1 == l && with_ops {
+            painted.push_str(&caret);
+        }
+    }
+
     pub fn add_char(&mut self, c: char) {
-        if let Some((pos, mut ops)) = self.options.take() {
-            if let Some(n) = ui::char_as_int(c) {
-                if ops.len() <= 10 {
-                    match ops.get(n) {
-                        Some(v) => {
-                            //TODO -- make so cursor moves with changes
-                            self.cursor.replace_range(pos, v);
-                            self.clear_help();
-                        }
-                        None => {
-                            self.message = Some("Selection Not Valid".to_string());
-                            self.options = Some((pos, ops));
-                        }
-                    }
-                } else {
-                    //More than 10 options
-                    ops = ops.into_iter().skip(n * 10).take(10).collect();
-                    self.options = Some((pos, ops))
-                }
-                return;
+        self.message = None;
+        self.error = None;
+        if c == ' ' {
+            self.expand_abbr();
+        }
+        if c == '\n' && !self.indent.is_empty() {
+            self.add_newline();
+        } else if !self.add_pair(c) {
+            self.dedent(c);
+            self.cursor.add_char(c);
+        }
+        self.refilter();
+    }
+
+    /// Adds a new line indented to its depth. Between a pair like "{}",
+    /// the closer goes on a line of its own
+    fn add_newline(&mut self) {
+        let idx = self.cursor.get_index();
+        let sc = brackets::scan_to(&self.cursor.s, idx);
+        if sc.in_quotes() {
+            self.cursor.add_char('\n');
+            return;
+        }
+        let d = sc.depth();
+        let rest = &self.cursor.s[idx..];
+        let line_rest = rest.split('\n').next().unwrap_or("");
+        let next = line_rest.trim_start().chars().next();
+        let ind = format!("\n{}", self.indent.repeat(d));
+        match next {
+            Some(c) if d > 0 && sc.closes(c) => {
+                let gap = line_rest.len() - line_rest.trim_start().len();
+                let close_ind = format!("\n{}", self.indent.repeat(d - 1));
+                self.cursor
+                    .replace_range(idx..idx + gap, &format!("{}{}", ind, close_ind));
+                self.cursor.utf8_hit(idx + ind.len());
             }
+            _ => self.cursor.replace_range(idx..idx, &ind),
+        }
+    }
+
+    /// Whether Enter should add a line rather than run, which is when the cursor is
+    /// inside a block, and either the block is empty, "{|}", or lines follow
+    pub fn enter_adds_line(&self) -> bool {
+        let idx = self.cursor.get_index();
+        let sc = brackets::scan_to(&self.cursor.s, idx);
+        if !sc.open.iter().any(|(c, _)| *c == '{') {
+            return false;
+        }
+        let (before, after) = self.cursor.s.split_at(idx);
+        after.contains('\n')
+            || (before.trim_end().ends_with('{') && after.trim_start().starts_with('}'))
+    }
+
+    /// Brings a closer typed at the start of a line back to the depth of its opener
+    fn dedent(&mut self, c: char) {
+        let idx = self.cursor.get_index();
+        let start = self.cursor.s[..idx].rfind('\n').map(|n| n + 1);
+        let start = match (start, self.indent.is_empty()) {
+            (Some(n), false) if self.cursor.s[n..idx].trim().is_empty() => n,
+            _ => return,
+        };
+        let sc = brackets::scan_to(&self.cursor.s, idx);
+        //Quotes don't count towards depth, and lines inside them keep their spaces
+        if c == '"' || sc.in_quotes() {
+            return;
+        }
+        if sc.closes(c) {
+            let ind = self.indent.repeat(sc.depth().saturating_sub(1));
+            self.cursor.replace_range(start..idx, &ind);
+        }
+    }
+
+    /// Closes an opening bracket or quote as it is typed, or steps over the closer
+    /// if it is already there. Returns false if c should just be added
+    fn add_pair(&mut self, c: char) -> bool {
+        let idx = self.cursor.get_index();
+        let (before, after) = self.cursor.s.split_at(idx);
+        let next = after.chars().next();
+        let sc = brackets::scan_to(&self.cursor.s, idx);
+        //Nothing pairs in a raw string, but its closing quote can be stepped over
+        if sc.raw {
+            return c == '"' && next == Some('"') && self.cursor.right();
+        }
+        let is_closer = match sc.open.last() {
+            Some(&(o, _)) => sc.closes(c) && self.pairs.contains(o),
+            None => false,
+        };
+        if is_closer && next == Some(c) {
+            return self.cursor.right();
+        }
+        //On an empty line above a closer that was put on its own line
+        let gap = after.len() - after.trim_start().len();
+        let line_start = before.rfind('\n');
+        if let (true, Some(ls), Some(nl)) = (is_closer, line_start, after[..gap].rfind('\n')) {
+            if before[ls..].trim().is_empty() && after[gap..].starts_with(c) {
+                let nl = idx + nl;
+                self.cursor.replace_range(ls..nl, "");
+                let close = idx + gap - (nl - ls);
+                self.cursor.utf8_hit(close + c.len_utf8());
+                return true;
+            }
+        }
+        let cl = match brackets::closer(c) {
+            Some(cl) if self.pairs.contains(c) => cl,
+            _ => return false,
+        };
+        //In quotes only "$(", "$[" and "${" are brackets
+        if before.ends_with('\\') || (sc.in_quotes() && (c == '"' || !before.ends_with('$'))) {
+            return false;
+        }
+        if !matches!(
+            next,
+            None | Some(' ' | '\t' | '\n' | ';' | ')' | ']' | '}' | '"')
+        ) {
+            return false;
         }
         self.cursor.add_char(c);
-        self.clear_help();
+        self.cursor.add_char(cl);
+        self.cursor.left();
+        true
+    }
+
+    pub fn backspace(&mut self) {
+        self.message = None;
+        self.error = None;
+        //Deleting an opener with nothing in the pair deletes its closer
+        let idx = self.cursor.get_index();
+        if let Some(p) = self.cursor.s[..idx].chars().last() {
+            let pos = idx - p.len_utf8();
+            if self.pairs.contains(p)
+                && brackets::closer(p) == self.cursor.s[idx..].chars().next()
+                && brackets::scan(&self.cursor.s).pairs.get(&pos) == Some(&idx)
+            {
+                self.cursor.del_char();
+            }
+        }
+        self.cursor.backspace();
+        self.refilter();
+    }
+
+    /// Moves the cursor to the bracket matching the one at, or just before, it
+    pub fn jump_match(&mut self) -> bool {
+        match brackets::near_match(&self.cursor.s, self.cursor.get_index()) {
+            Some(n) => {
+                self.cursor.utf8_hit(n);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Replaces an abbreviation just before the cursor with what it stands for,
+    /// if it is in command position. Returns true if the line changed
+    pub fn expand_abbr(&mut self) -> bool {
+        if self.abbrs.is_empty() {
+            return false;
+        }
+        let idx = self.cursor.get_index();
+        if !matches!(self.cursor.s[idx..].chars().next(), None | Some(' ' | '\n')) {
+            return false;
+        }
+        let ci = match self.cursor.item_over() {
+            Ok((_, ci)) if ci.item == Item::Command => ci,
+            _ => return false,
+        };
+        let word = match self.cursor.s.get(ci.start..idx) {
+            Some(w) => w,
+            None => return false,
+        };
+        let start = ci.start + word.len() - word.trim_start().len();
+        match self.abbrs.get(word.trim_start()) {
+            Some(full) => {
+                let full = full.clone();
+                self.cursor.replace_range(start..idx, &full);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Narrows the menu to what has been typed since it opened
+    fn refilter(&mut self) {
+        if let Some(m) = &mut self.menu {
+            let keep = match self.cursor.s.get(m.start..self.cursor.get_index()) {
+                Some(word) => m.filter(word),
+                None => false,
+            };
+            if !keep {
+                self.menu = None;
+            }
+        }
+    }
+
+    /// Handles keys while the menu is open.
+    /// Returns false for keys that should still be handled as normal
+    pub fn menu_key(&mut self, k: Key) -> bool {
+        let m = match &mut self.menu {
+            Some(m) => m,
+            None => return false,
+        };
+        match k {
+            Key::Char('\t') | Key::Down => m.next(),
+            Key::BackTab | Key::Up => m.prev(),
+            Key::Char('\n') => {
+                let sel = m.selected().map(|c| (m.start, m.tail, c.text.clone()));
+                self.menu = None;
+                //With nothing picked, Enter runs the line as usual
+                let (start, tail, text) = match sel {
+                    Some(v) => v,
+                    None => return false,
+                };
+                let end = (self.cursor.get_index() + tail).min(self.cursor.s.len());
+                self.cursor.replace_range(start..end, &text);
+            }
+            Key::Char(_) | Key::Backspace => return false,
+            _ => {
+                self.menu = None;
+                return false;
+            }
+        }
+        true
     }
 
     pub fn del_char(&mut self) {
@@ -228,14 +545,22 @@ impl Prompt {
     }
 }
 
-pub fn build_line<'a>(l: &str, hl: &Highlight) -> String {
+/// Highlights l, marking the char at mark with the match style
+pub fn build_line(l: &str, hl: &Highlight, mark: Option<usize>) -> String {
+    let res = paint_line(l, hl);
+    let res = match (mark, hl.match_style()) {
+        (Some(n), Some(on)) => ui::mark_char(&res, n, &on),
+        _ => res,
+    };
+    res.replace("\n", "\n... ")
+}
+
+fn paint_line(l: &str, hl: &Highlight) -> String {
     match hl.highlight(l) {
         Ok(s) => {
             //let s = bogobble::partial::mark_list::mark_str(&v, l).expect("Marking out of String");
             //       println!("parsed line = '{}'\r\n\n", s);
-            let res = format!("{}{}", s, color::Fg(color::Reset));
-            let res = res.replace("\n", "\n... ");
-            res
+            format!("{}{}", s, termion::style::Reset)
         }
         Err(e) => {
             //      println!("Parse Error '{}',\r\n\n\n", e);
@@ -245,16 +570,120 @@ pub fn build_line<'a>(l: &str, hl: &Highlight) -> String {
                     color::Fg(color::LightRed),
                     l,
                     color::Fg(color::Reset),
-                )
-                .replace("\n", "\n... "),
+                ),
                 Some(n) => format!(
                     "{}{}{}{}",
-                    build_line(&l[0..n], hl),
+                    paint_line(&l[0..n], hl),
                     color::Fg(color::LightRed),
-                    &l[n..].replace("\n", "\n... "),
+                    &l[n..],
                     color::Fg(color::Reset)
                 ),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::menu::Candidate;
+    #[test]
+    fn abbr_in_command_position() {
+        let mut p = Prompt::new(">>".to_string());
+        p.abbrs
+            .insert("gco".to_string(), "git checkout".to_string());
+        for c in "gco gco && gco ".chars() {
+            p.add_char(c);
+        }
+        assert_eq!(p.cursor.s, "git checkout gco && git checkout ");
+    }
+
+    #[test]
+    fn pairs_close_and_step_over() {
+        let mut p = Prompt::new(">>".to_string());
+        p.pairs = "([{\"".to_string();
+        for c in "echo \"$(ls)\" [a]".chars() {
+            p.add_char(c);
+        }
+        assert_eq!(p.cursor.s, "echo \"$(ls)\" [a]");
+        assert!(p.cursor.is_end());
+        for c in " {".chars() {
+            p.add_char(c);
+        }
+        assert_eq!(p.cursor.s, "echo \"$(ls)\" [a] {}");
+        p.backspace();
+        assert_eq!(p.cursor.s, "echo \"$(ls)\" [a] ");
+        assert!(!p.jump_match());
+        p.backspace();
+        p.jump_match();
+        assert_eq!(p.cursor.get_index(), 13);
+    }
+
+    #[test]
+    fn indent_blocks() {
+        let mut p = Prompt::new(">>".to_string());
+        p.pairs = "{".to_string();
+        p.indent = "  ".to_string();
+        for c in "for x in * {\nif $x {\necho $x\n}".chars() {
+            p.add_char(c);
+        }
+        assert_eq!(p.cursor.s, "for x in * {\n  if $x {\n    echo $x\n  }\n}");
+        p.cursor.to_end();
+        p.add_char('\n');
+        p.add_char('}');
+        assert_eq!(
+            p.cursor.s,
+            "for x in * {\n  if $x {\n    echo $x\n  }\n}\n}"
+        );
+
+        p.cursor = Cursor::at_end(String::new());
+        p.pairs = String::new();
+        for c in "if a {\necho\n}".chars() {
+            p.add_char(c);
+        }
+        assert_eq!(p.cursor.s, "if a {\n  echo\n}");
+    }
+
+    #[test]
+    fn quote_closed_on_new_line() {
+        let mut p = Prompt::new(">>".to_string());
+        p.indent = "    ".to_string();
+        for pairs in ["", "([{\""] {
+            p.cursor = Cursor::at_end(String::new());
+            p.pairs = pairs.to_string();
+            for c in "echo \"a\n\"".chars() {
+                p.add_char(c);
+            }
+            assert_eq!(p.cursor.s, "echo \"a\n\"");
+        }
+    }
+
+    #[test]
+    fn caret_after_wide_chars() {
+        let mut p = Prompt::new(">>".to_string());
+        p.cursor = Cursor::at_end("echo 你们 }".to_string());
+        p.error = Some(Diagnosis {
+            index: p.cursor.s.find('}').unwrap(),
+            msg: "here".to_string(),
+        });
+        let b = console::strip_ansi_codes(&p.build(&p.cursor.s, true)).to_string();
+        let caret = b.lines().find(|l| l.contains('^')).unwrap();
+        assert_eq!(caret.find('^'), Some(ui::text_width(">>echo 你们 ")));
+    }
+
+    #[test]
+    fn enter_without_selection_runs() {
+        let mut p = Prompt::new(">>".to_string());
+        p.cursor = Cursor::at_end("ca".to_string());
+        let cands = vec![Candidate::new("cat"), Candidate::new("cargo")];
+        p.menu = Some(Menu::new(0, 0, cands.clone()));
+        assert!(!p.menu_key(Key::Char('\n')));
+        assert!(p.menu.is_none());
+        assert_eq!(p.cursor.s, "ca");
+
+        p.menu = Some(Menu::new(0, 0, cands));
+        assert!(p.menu_key(Key::Down));
+        assert!(p.menu_key(Key::Char('\n')));
+        assert_eq!(p.cursor.s, "cat");
+    }
+}
@@ -0,0 +1,157 @@
+//! Runs the commands in prompts on other threads, so a slow one can't hold up the prompt.
+//!
+//! Each command is waited on for "RU_PROMPT_TIMEOUT" ms (default 200). One that takes
+//! longer shows what it gave for the last prompt, or "RU_PROMPT_PLACEHOLDER" if it
+//! has never finished, and the prompt is drawn again when it does.
+use crate::args::Arg;
+use crate::data::Data;
+use crate::events;
+use crate::store::Store;
+use err_tools::*;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+#[derive(Default)]
+struct Entry {
+    val: Option<Result<Data, String>>,
+    /// Which prompt val was found for
+    gen: usize,
+    running: bool,
+    /// The prompt was drawn without it, so it needs drawing again when it's done
+    waiting: bool,
+}
+
+static CACHE: Mutex<BTreeMap<String, Entry>> = Mutex::new(BTreeMap::new());
+static GEN: AtomicUsize = AtomicUsize::new(1);
+
+fn cache() -> MutexGuard<'static, BTreeMap<String, Entry>> {
+    CACHE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Starts a new prompt, so commands run again rather than using the last results
+pub fn next_prompt() {
+    GEN.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Which prompt this is, for anything else cached once per prompt
+pub fn generation() -> usize {
+    GEN.load(Ordering::SeqCst)
+}
+
+fn timeout(store: &Store) -> Duration {
+    let ms = store
+        .get("RU_PROMPT_TIMEOUT")
+        .and_then(|d| d.to_string().parse().ok())
+        .unwrap_or(200);
+    Duration::from_millis(ms)
+}
+
+fn found(v: &Option<Result<Data, String>>, store: &Store) -> anyhow::Result<Data> {
+    match v {
+        Some(Ok(d)) => Ok(d.clone()),
+        Some(Err(e)) => e_string(e.clone()),
+        None => Ok(store
+            .get("RU_PROMPT_PLACEHOLDER")
+            .unwrap_or_else(|| Data::Str("…".to_string()))),
+    }
+}
+
+/// Runs a prompt command, waiting no longer than the timeout
+fn command(a: &Arg, store: &Store) -> anyhow::Result<Data> {
+    let key = format!("{:?}", a);
+    let gen = generation();
+    let mut c = cache();
+    let e = c.entry(key.clone()).or_default();
+    if e.gen == gen && !e.running {
+        return found(&e.val, store);
+    }
+    if e.running {
+        e.waiting = true;
+        return found(&e.val, store);
+    }
+    e.running = true;
+    e.waiting = false;
+    drop(c);
+
+    let (tx, rx) = channel();
+    let vars = store.as_map();
+    let a2 = a.clone();
+    let k2 = key.clone();
+    std::thread::spawn(move || {
+        let mut st = Store::new();
+        for (k, v) in vars {
+            st.let_set(k, v);
+        }
+        //The not found hook would share the terminal
+        st.let_set("RU_NOT_FOUND".to_string(), Data::Str(String::new()));
+        let res = a2.run(&mut st, 1).map_err(|e| e.to_string());
+        let mut c = cache();
+        let e = c.entry(k2).or_default();
+        e.val = Some(res);
+        e.gen = gen;
+        e.running = false;
+        let wake = e.waiting;
+        drop(c);
+        tx.send(()).ok();
+        if wake {
+            events::redraw();
+        }
+    });
+
+    rx.recv_timeout(timeout(store)).ok();
+    let mut c = cache();
+    let e = c.entry(key).or_default();
+    if e.running {
+        e.waiting = true;
+    }
+    found(&e.val, store)
+}
+
+/// Runs a prompt string, with commands run as above
+pub fn run(a: &Arg, store: &mut Store) -> anyhow::Result<Data> {
+    match a {
+        Arg::Command(_) | Arg::ArrCommand(_) => command(a, store),
+        Arg::StringExpr(v) => {
+            let mut s = String::new();
+            for a in v {
+                s.push_str(&run(a, store)?.to_string());
+            }
+            Ok(Data::Str(s))
+        }
+        a => a.run(store, 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser;
+    use bogobble::traits::*;
+    #[test]
+    fn slow_commands_show_placeholder() {
+        let mut store = Store::new();
+        store.let_set(
+            "RU_PROMPT_TIMEOUT".to_string(),
+            Data::Str("100".to_string()),
+        );
+        store.let_set(
+            "RU_PROMPT_PLACEHOLDER".to_string(),
+            Data::Str("?".to_string()),
+        );
+        let slow = "sleep 0.5; echo slow".to_string();
+        store.let_set("SLOW".to_string(), Data::Str(slow));
+        let a = parser::QuotedString
+            .parse_s("<$(sh -c $SLOW)|$(echo quick)>")
+            .unwrap();
+        next_prompt();
+        assert_eq!(run(&a, &mut store).unwrap().to_string(), "<?|quick>");
+        std::thread::sleep(Duration::from_millis(800));
+        assert_eq!(run(&a, &mut store).unwrap().to_string(), "<slow|quick>");
+        //The next prompt shows the last value until the new one is done
+        next_prompt();
+        assert_eq!(run(&a, &mut store).unwrap().to_string(), "<slow|quick>");
+    }
+}
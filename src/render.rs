@@ -0,0 +1,65 @@
+//! Draws the prompt, and remembers what it drew, so it can be cleared or drawn again.
+//!
+//! Where the cursor goes is worked out from the display width of each char, wrapping
+//! as the terminal does. What was drawn is kept as text, not as a count of lines,
+//! so after a resize it can be measured again at the new width, as the terminal
+//! will have rewrapped it, and everything below cleared before drawing again.
+use crate::ui;
+use termion::{clear, cursor};
+
+fn width() -> usize {
+    termion::terminal_size().map(|(w, _)| w).unwrap_or(50) as usize
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Screen {
+    /// The part of what was drawn before the cursor
+    upto: String,
+}
+
+/// The row and column of the cursor after s, at width w, moving a full line on
+/// to the start of the next, as the cursor sits there
+fn cursor_at(s: &str, w: usize) -> (usize, usize) {
+    match ui::end_pos(s, w) {
+        (r, c) if c >= w => (r + 1, 0),
+        rc => rc,
+    }
+}
+
+impl Screen {
+    /// Draws full, with the cursor at the end of upto, which must start full
+    pub fn draw(&mut self, full: String, upto: String) {
+        let w = width();
+        ui::print(&full);
+        let (mut fr, _) = ui::end_pos(&full, w);
+        let (cr, cc) = cursor_at(&upto, w);
+        //Only at the very end of a full line, so a space wraps onto the next.
+        //A soft wrap rather than a new line, so it rewraps with the rest on resize
+        if cr > fr {
+            print!(" ");
+            fr += 1;
+        }
+        if fr > cr {
+            print!("{}", cursor::Up((fr - cr) as u16));
+        }
+        print!("\r");
+        if cc > 0 {
+            print!("{}", cursor::Right(cc as u16));
+        }
+        self.upto = upto;
+    }
+
+    /// Clears what was drawn, leaving the cursor where it started
+    pub fn clear(&self) {
+        let (r, _) = cursor_at(&self.upto, width());
+        if r > 0 {
+            print!("{}", cursor::Up(r as u16));
+        }
+        print!("\r{}", clear::AfterCursor);
+    }
+
+    /// Forgets what was drawn, once it is to be left on screen
+    pub fn forget(&mut self) {
+        self.upto.clear();
+    }
+}
@@ -0,0 +1,174 @@
+//! Prompts built from segments.
+//!
+//! A prompt setting can be one string, or a list whose items are strings, or "@name"
+//! for a segment. A segment is a function, either one built in, or a prompt string
+//! in the "RU_SEGMENTS" map. A segment that fails or is empty is left out.
+use crate::data::Data;
+use crate::parser;
+use crate::prompt_cmd;
+use crate::store::Store;
+use bogobble::traits::*;
+use bogobble::*;
+
+pub type SegFn = fn(&Store) -> Option<String>;
+
+pub const BUILTIN: &[(&str, SegFn)] = &[
+    ("cwd", cwd),
+    ("dir", dir),
+    ("user", user),
+    ("host", host),
+    ("git", git),
+    ("status", status),
+    ("duration", |s| var(s, "RU_DURATION")),
+    ("time", |s| var(s, "RU_TIME")),
+    ("jobs", jobs),
+    ("venv", |s| var(s, "RU_VENV")),
+    ("nix", |s| var(s, "RU_NIX_SHELL").map(|_| "nix".to_string())),
+];
+
+fn var(store: &Store, k: &str) -> Option<String> {
+    let v = store.get(k)?.to_string();
+    match v.is_empty() {
+        true => None,
+        false => Some(v),
+    }
+}
+
+fn pwd(store: &Store) -> Option<String> {
+    match store.get("PWD") {
+        Some(p) => Some(p.to_string()),
+        None => Some(std::env::current_dir().ok()?.display().to_string()),
+    }
+}
+
+/// The working directory, with "~" for home
+fn cwd(store: &Store) -> Option<String> {
+    let p = pwd(store)?;
+    let home = store.get("HOME").map(|h| h.to_string()).unwrap_or_default();
+    match p.strip_prefix(&home) {
+        Some(rest) if !home.is_empty() && (rest.is_empty() || rest.starts_with('/')) => {
+            Some(format!("~{}", rest))
+        }
+        _ => Some(p),
+    }
+}
+
+/// The last part of the working directory
+fn dir(store: &Store) -> Option<String> {
+    let p = pwd(store)?;
+    match p.rsplit('/').next() {
+        Some("") | None => Some(p),
+        Some(d) => Some(d.to_string()),
+    }
+}
+
+fn user(store: &Store) -> Option<String> {
+    store
+        .get("USER")
+        .or_else(|| store.get("USERNAME"))
+        .map(|u| u.to_string())
+}
+
+fn host(store: &Store) -> Option<String> {
+    if let Some(h) = store.get("HOSTNAME") {
+        return Some(h.to_string());
+    }
+    let h = std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| std::fs::read_to_string("/etc/hostname"))
+        .ok()?;
+    Some(h.trim().to_string())
+}
+
+/// The branch, with "*" when there are changes
+fn git(store: &Store) -> Option<String> {
+    let b = var(store, "RU_GIT_BRANCH")?;
+    match store.get("RU_GIT_DIRTY") {
+        Some(Data::Bool(true)) => Some(format!("{}*", b)),
+        _ => Some(b),
+    }
+}
+
+/// The last exit status, unless it was 0
+fn status(store: &Store) -> Option<String> {
+    var(store, "RU_STATUS").filter(|s| s != "0")
+}
+
+fn jobs(store: &Store) -> Option<String> {
+    var(store, "RU_JOBS").filter(|s| s != "0")
+}
+
+/// Runs the vars and commands in a prompt string, commands in the background.
+/// One that doesn't parse, all the way, is shown as it is
+pub fn eval_str(s: &str, store: &mut Store) -> anyhow::Result<String> {
+    match (parser::QuotedString, EOI).parse_s(s) {
+        Ok((v, _)) => Ok(prompt_cmd::run(&v, store)?.to_string()),
+        Err(_) => Ok(s.to_string()),
+    }
+}
+
+/// The text of the segment called name, if it has any
+pub fn segment(name: &str, store: &mut Store) -> Option<String> {
+    let mut user_def = None;
+    store.do_with("RU_SEGMENTS", |d| {
+        if let Data::Map(m) = d {
+            user_def = m.get(name).map(|v| v.to_string());
+        }
+    });
+    let res = match user_def {
+        Some(s) => eval_str(&s, store).ok()?,
+        None => (BUILTIN.iter().find(|(k, _)| *k == name)?.1)(store)?,
+    };
+    match res.is_empty() {
+        true => None,
+        false => Some(res),
+    }
+}
+
+/// Builds a prompt from a prompt setting
+pub fn render(d: &Data, store: &mut Store) -> anyhow::Result<String> {
+    let l = match d {
+        Data::List(l) => l,
+        d => return eval_str(&d.to_string(), store),
+    };
+    let mut res = String::new();
+    for item in l {
+        let s = item.to_string();
+        match s.strip_prefix('@') {
+            Some(name) => res.extend(segment(name, store)),
+            None => res.push_str(&eval_str(&s, store)?),
+        }
+    }
+    Ok(res)
+}
+
+/// The prompt setting called name, or def if it is not set
+pub fn prompt_var(name: &str, def: &str, store: &mut Store) -> String {
+    match store.get(name) {
+        Some(d) => render(&d, store).unwrap_or_else(|_| format!("PromptErr:{}", def)),
+        None => def.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn segments_in_lists() {
+        let mut store = Store::new();
+        store.let_set("HOME".to_string(), Data::Str("/home/me".to_string()));
+        store.let_set("PWD".to_string(), Data::Str("/home/me/src".to_string()));
+        store.let_set("X".to_string(), Data::Str("x".to_string()));
+        let mut segs = std::collections::BTreeMap::new();
+        segs.insert("ex".to_string(), Data::Str("<$X>".to_string()));
+        segs.insert("none".to_string(), Data::Str("$NOT_SET".to_string()));
+        store.let_set("RU_SEGMENTS".to_string(), Data::Map(segs));
+        let p = Data::List(
+            ["@cwd", " ", "@dir", "@none", "@ex", " $X>"]
+                .iter()
+                .map(|s| Data::Str(s.to_string()))
+                .collect(),
+        );
+        assert_eq!(render(&p, &mut store).unwrap(), "~/src src<x> x>");
+        assert_eq!(prompt_var("NOT_SET", ">>", &mut store), ">>");
+    }
+}
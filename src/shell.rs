@@ -1,12 +1,23 @@
 //! Some options for statements to run, or persistent data
-use crate::cursor::Cursor;
+use crate::comp_spec::split_words;
+use crate::computed;
+use crate::cursor::{Cursor, CursorItem};
+use crate::data::Data;
+use crate::diagnose::{diagnose, is_incomplete};
+use crate::highlight::{Highlight, Known};
+use crate::history::*;
+use crate::menu::{Candidate, Menu};
 use crate::partial::Item;
 use crate::Action;
 use bogobble::traits::*;
 use termion::event::Key;
 
+use crate::prompt_cmd;
+use crate::segments;
+use crate::signals;
 use crate::store::Store;
 use crate::tab_complete::*;
+use crate::timing::{self, Timer};
 use crate::{parser, prompt::Prompt, RT};
 use ru_history::HistoryStore;
 //use std::io::Read;
@@ -18,20 +29,25 @@ pub struct Shell {
     pub prompt: Prompt,
     pub store: Store,
     pub history: HistoryStore,
+    last_saved: Option<String>,
 }
 
 impl Shell {
     /// Invariants : Settings must always have at least one layer in scope.
     pub fn new() -> Shell {
-        let mut history = HistoryStore::new();
-        let mut prompt = Prompt::new(">>".to_string());
-        if let Err(e) = load_history(2, &mut history) {
-            prompt.message = Some(e.to_string());
-        }
         Shell {
-            prompt,
+            prompt: Prompt::new(">>".to_string()),
             store: Store::new(),
-            history,
+            history: HistoryStore::new(),
+            last_saved: None,
+        }
+    }
+
+    /// (Re)loads history for the number of months set by RU_HISTORY_MONTHS
+    pub fn load_history(&mut self) {
+        self.history = HistoryStore::new();
+        if let Err(e) = load_history(months(&self.store), &mut self.history) {
+            self.prompt.message = Some(e.to_string());
         }
     }
 
@@ -45,21 +61,36 @@ impl Shell {
     fn tab_complete(&mut self) {
         self.prompt.clear_help();
 
-        let (cmd, ci) = match self.prompt.cursor.item_over() {
+        let (cmd, mut ci) = match self.prompt.cursor.item_over() {
             Ok(ci) => ci,
             Err(e) => {
                 self.prompt.message = Some(format!("{}", e));
                 return;
             }
         };
-        let cmd = cmd.on_str(&self.prompt.cursor.s);
-        let s = ci.on_str(&self.prompt.cursor.s);
+        let line = &self.prompt.cursor.s;
+        //Items can start or end with the space around them
+        let item_s = ci.on_str(line);
+        ci.start += item_s.len() - item_s.trim_start().len();
+        if let (Item::Command | Item::Keyword, true) = (ci.item, item_s.ends_with(' ')) {
+            ci = CursorItem {
+                item: Item::Arg,
+                start: self.prompt.cursor.get_index(),
+                fin: None,
+            };
+        }
+        let words = split_words(line.get(cmd.start..ci.start).unwrap_or(""));
+        let cmd = words.first().map(String::as_str).unwrap_or("");
+        let s = ci.on_str(line);
 
         let complete = match ci.item {
             Item::String | Item::Arg | Item::Path => {
-                let mut v = tab_complete_path(s);
-                match tab_complete_args(s, cmd, &mut self.store) {
-                    Ok(r) => v.extend(r),
+                match tab_complete_args(s, &words, &mut self.store) {
+                    Ok(Some(v)) => v,
+                    Ok(None) => tab_complete_path(s)
+                        .into_iter()
+                        .map(Candidate::from)
+                        .collect(),
                     Err(e) => {
                         if let Some(db) = self.store.get("RU_DEBUG") {
                             if db.is_true() {
@@ -67,18 +98,18 @@ impl Shell {
                                     Some(format!("tab complete for {} err : '{}'", cmd, e))
                             }
                         }
+                        tab_complete_path(s)
+                            .into_iter()
+                            .map(Candidate::from)
+                            .collect()
                     }
                 }
-                v
             }
-            Item::Keyword | Item::Command => tab_complete_prog(s),
-            Item::Ident => {
-                self.prompt.message = Some(format!(
-                    "Should be able to complete {:?} :'{}'",
-                    ci.item,
-                    ci.on_str(&self.prompt.cursor.s)
-                ));
-                return;
+            Item::Keyword | Item::Command => tab_complete_command(s, &self.store),
+            Item::Ident => tab_complete_var("", s, &self.store),
+            Item::Var => {
+                let name = s.trim_start_matches('$').trim_start_matches('{');
+                tab_complete_var(&s[..s.len() - name.len()], name, &self.store)
             }
             _ => {
                 self.prompt.message = Some(format!(
@@ -101,116 +132,296 @@ impl Shell {
             1 => self
                 .prompt
                 .cursor
-                .replace_range(ci.to_ranger(), &complete[0]),
-            _ => self.prompt.options = Some((ci.to_ranger(), complete)),
+                .replace_range(ci.to_ranger(), &complete[0].text),
+            _ => {
+                let idx = self.prompt.cursor.get_index();
+                let tail = ci
+                    .fin
+                    .unwrap_or(self.prompt.cursor.s.len())
+                    .saturating_sub(idx);
+                let mut m = Menu::new(ci.start, tail, complete);
+                m.filter(s);
+                self.prompt.menu = Some(m);
+            }
         }
     }
 
-    pub fn re_highlight(&mut self) {
-        match self.store.get("RU_HIGHLIGHT") {
-            Some(s) => self.prompt.set_highlight(&s.to_string()),
-            None => self.prompt.set_highlight(""),
+    /// Finds the most likely history entry starting with the current line
+    fn suggest(&mut self) {
+        let s = &self.prompt.cursor.s;
+        self.prompt.suggestion = match s.is_empty() {
+            true => None,
+            false => self
+                .history
+                .complete(s, &ru_history::here(), 8)
+                .iter()
+                .filter_map(|c| c.strip_prefix(s.as_str()))
+                .find(|c| !c.is_empty())
+                .map(String::from),
+        };
+    }
+
+    /// Lists the aliases the line will expand. On unless RU_SHOW_ALIAS is set,
+    /// to anything but true
+    fn preview_aliases(&mut self) {
+        let show = self.store.get("RU_SHOW_ALIAS").map(|d| d.is_true());
+        if show == Some(false) {
+            self.prompt.preview = None;
+            return;
         }
+        let uses = crate::alias::uses(&self.prompt.cursor.s, &self.store);
+        self.prompt.preview = match uses.is_empty() {
+            true => None,
+            false => Some(
+                uses.iter()
+                    .map(|(k, v)| format!("{} = {}", k, v))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+        };
     }
 
-    pub fn on_enter(&mut self, rt: &mut RT) {
-        let c_line = &self.prompt.cursor.s;
-        let alias = crate::store::alias(c_line, &self.store);
-        let parse_res = match &alias {
-            Some(s) => parser::Lines.parse_s(&s).map_err(|e| e.strung()),
-            None => parser::Lines.parse_s(c_line).map_err(|e| e.strung()),
+    /// Runs an edit on the prompt, then updates the suggestion and alias preview to match
+    fn edit<F: Fn(&mut Prompt)>(&mut self, rt: &mut RT, f: F) {
+        self.do_print(rt, |sh| {
+            f(&mut sh.prompt);
+            sh.suggest();
+            sh.preview_aliases();
+        })
+    }
+
+    pub fn re_highlight(&mut self) {
+        let known = Known::from_store(&self.store);
+        self.prompt
+            .set_highlight(Highlight::from_store(&self.store).map(|h| h.with_known(known)));
+    }
+
+    /// Runs the line if it parses, or if force is set, showing why it didn't.
+    /// Otherwise a line that is only unfinished gets a new line, and one that
+    /// can't be fixed by adding more gets its error shown.
+    pub fn on_enter(&mut self, rt: &mut RT, force: bool) {
+        if self.prompt.cursor.is_end() && self.prompt.expand_abbr() {
+            self.prompt.unprint(rt);
+            self.prompt.print(rt);
+        }
+        if !force && self.prompt.enter_adds_line() {
+            return self.edit(rt, |p| p.add_char('\n'));
+        }
+        let c_line = self.prompt.cursor.s.clone();
+        let (v, err) = match parser::Lines.parse_s(&c_line).map_err(|e| e.strung()) {
+            Ok(v) => (v, None),
+            Err(e) if force => (Vec::new(), Some(diagnose(&c_line, &e))),
+            Err(e) => {
+                return match is_incomplete(&c_line, &e) {
+                    true => self.do_print(rt, |sh| sh.prompt.add_char('\n')),
+                    false => self.do_print(rt, |sh| {
+                        sh.prompt.error = Some(diagnose(&c_line, &e));
+                    }),
+                };
+            }
         };
+        //A forced line that doesn't parse can't be run again, so isn't kept
+        let mut saved = false;
+        if !v.is_empty() {
+            self.prompt.guess_man.add_recent(c_line.clone());
+            saved = self.record_history();
+        }
+        if self.prompt.transient.is_some() {
+            self.prompt.collapse(rt);
+        } else if !self.prompt.cursor.is_end()
+            || self.prompt.error.is_some()
+            || self.prompt.preview.is_some()
+        {
+            //The output would otherwise be written over the error or alias preview
+            self.prompt.unprint(rt);
+            self.prompt.error = None;
+            self.prompt.preview = None;
+            self.prompt.print_end(rt);
+        }
 
-        match parse_res {
-            Ok(v) => {
-                if v.len() > 0 {
-                    self.prompt.guess_man.add_recent(c_line.clone());
-                    self.history
-                        .add_cmd(&c_line, &ru_history::here(), ru_history::now());
+        rt.suspend_raw_mode().ok();
+        print!("\n\r");
+        rt.flush().ok();
+        if let Some(d) = err {
+            let (l, c) = d.line_col(&c_line);
+            print!("Err - {}:{}: {}\n\r", l, c, d.msg);
+        }
+        let hist_reloads = reloads();
+        let (timer, when) = (Timer::start(), ru_history::now());
+        let ran = !v.is_empty();
+        signals::clear();
+        for s in v {
+            if signals::interrupted() {
+                break;
+            }
+            match s.run(&mut self.store) {
+                Ok(false) => {
+                    print!("\n\rOK - fail\n\r");
+                    computed::set_status(computed::last_status().max(1));
                 }
-                if !self.prompt.cursor.is_end() {
-                    self.prompt.unprint(rt);
-                    self.prompt.print_end(rt);
+                Err(e) => {
+                    print!("\n\rErr - {}\n\r", e);
+                    computed::set_status(1);
                 }
+                Ok(true) => computed::set_status(0),
+            }
+        }
+        if signals::interrupted() {
+            computed::set_status(130);
+        }
+        if ran {
+            let times = timer.stop();
+            computed::set_times(times);
+            let status = computed::last_status();
+            let tm = Timing {
+                cmd: c_line.clone(),
+                when,
+                times,
+                status,
+            };
+            if saved && save_timing(&tm).is_err() {
+                print!("\n\rCould not save timing");
+            }
+            timing::notify(&c_line, &times, &self.store);
+        }
+        rt.activate_raw_mode().ok();
+        if hist_reloads != reloads() {
+            self.load_history();
+        }
+        self.reset(rt);
+        self.prompt.unprint(rt);
+        self.re_highlight();
 
-                rt.suspend_raw_mode().ok();
-                print!("\n\r");
-                rt.flush().ok();
-                for s in v {
-                    match s.run(&mut self.store) {
-                        Ok(false) => print!("\n\rOK - fail\n\r"),
-                        Err(e) => print!("\n\rErr - {}\n\r", e),
-                        _ => {}
-                    }
-                }
-                rt.activate_raw_mode().ok();
-                self.reset(rt);
-                self.prompt.unprint(rt);
-                if let (Some(s), Some(show)) = (alias, self.store.get("RU_SHOW_ALIAS")) {
-                    if show.is_true() {
-                        self.prompt.message = Some(format!("Alias = '{}'", s));
-                    }
-                }
-                self.re_highlight();
+        self.prompt.print(rt);
+    }
+
+    /// Drops the line being edited, leaving it on screen marked "^C"
+    fn cancel(&mut self, rt: &mut RT) {
+        self.prompt.unprint(rt);
+        self.prompt.clear_help();
+        self.prompt.error = None;
+        self.prompt.print_end(rt);
+        print!("^C\n\r");
+        self.reset(rt);
+    }
 
-                self.prompt.print(rt);
+    /// Adds the line to history, unless the rules ignore it. Returns whether it did
+    fn record_history(&mut self) -> bool {
+        let c_line = &self.prompt.cursor.s;
+        let rules = match IgnoreRules::from_store(&self.store) {
+            Ok(r) => r,
+            Err(e) => {
+                print!("\n\rHistory ignore rules error, not saved : {}", e);
+                return false;
             }
-            Err(_) => self.do_print(rt, |sh| sh.prompt.add_char('\n')),
+        };
+        if rules.ignores(c_line) {
+            return false;
+        }
+        if rules.dedupe && self.last_saved.as_ref() == Some(c_line) {
+            return false;
+        }
+        self.history
+            .add_cmd(c_line, &ru_history::here(), ru_history::now());
+        self.last_saved = Some(c_line.clone());
+        //Saved before running, so nothing is lost if the terminal closes
+        if save_history(&mut self.history).is_err() {
+            print!("\n\rCould not save history");
         }
+        true
     }
 
     pub fn reset(&mut self, rt: &mut RT) {
-        let pt = self
-            .store
-            .get("RU_PROMPT")
-            .map(|d| d.to_string())
-            .unwrap_or(String::from(">>"));
-        let pt = match parser::QuotedString.parse_s(&pt) {
-            Ok(v) => v
-                .run(&mut self.store, 1)
-                .map(|s| s.to_string())
-                .unwrap_or("PromptErr:>>".to_string()),
-            Err(_) => pt,
+        self.prompt.abbrs = match self.store.get("RU_ABBR") {
+            Some(Data::Map(m)) => m.into_iter().map(|(k, v)| (k, v.to_string())).collect(),
+            _ => Default::default(),
         };
+        self.prompt.pairs = match self.store.get("RU_AUTOPAIR") {
+            Some(d) => d.to_string(),
+            None => "([{\"".to_string(),
+        };
+        self.prompt.indent = match self.store.get("RU_INDENT") {
+            Some(d) => " ".repeat(d.to_string().parse().unwrap_or(0)),
+            None => "    ".to_string(),
+        };
+        self.prompt.show_match =
+            self.store.get("RU_SHOW_MATCH").map(|d| d.is_true()) != Some(false);
+        prompt_cmd::next_prompt();
+        let pt = self.build_prompts();
         self.prompt.reset(pt, rt);
         rt.flush().ok();
     }
 
+    /// Sets the right and transient prompts, and returns the main one
+    fn build_prompts(&mut self) -> String {
+        self.prompt.rprompt = segments::prompt_var("RU_RPROMPT", "", &mut self.store);
+        self.prompt.transient = match self.store.get("RU_TRANSIENT_PROMPT") {
+            Some(_) => Some(segments::prompt_var(
+                "RU_TRANSIENT_PROMPT",
+                ">>",
+                &mut self.store,
+            )),
+            None => None,
+        };
+        segments::prompt_var("RU_PROMPT", ">>", &mut self.store)
+    }
+
+    /// Draws the prompt again, with the results of any prompt commands that have finished
+    pub fn redraw_prompt(&mut self, rt: &mut RT) {
+        //Unprinting only uses what was printed, so the new prompts can be set first
+        let pt = self.build_prompts();
+        self.prompt.do_print(rt, |p| p.pr_line = pt);
+    }
+
     pub fn do_key(&mut self, k: Key, rt: &mut RT) -> anyhow::Result<Action> {
+        if self.prompt.menu.is_some() && self.prompt.do_print(rt, |p| p.menu_key(k)) {
+            return Ok(Action::Cont);
+        }
         match k {
+            Key::Ctrl('c') => self.cancel(rt),
             Key::Ctrl('d') => {
                 if let Err(_) = save_history(&mut self.history) {
                     println!("Could not save history\n\r");
                 }
                 return Ok(Action::Quit);
             }
-            Key::Char('\n') => self.on_enter(rt),
+            Key::Char('\n') => self.on_enter(rt, false),
+            Key::Alt('\r') | Key::Alt('\n') => self.on_enter(rt, true),
             Key::Char('\t') => self.do_print(rt, Shell::tab_complete),
-            Key::Char(c) => self.prompt.do_print(rt, |p| p.add_char(c)),
-            Key::Backspace => self.prompt.do_cursor(rt, Cursor::backspace),
-            Key::Delete => self.prompt.do_cursor(rt, Cursor::del_char),
-            Key::Ctrl('n') => self.prompt.do_print(rt, |p| p.add_char('\n')),
-            Key::Ctrl('h') => self.prompt.do_cursor(rt, Cursor::del_line),
+            Key::Char(c) => self.edit(rt, |p| p.add_char(c)),
+            Key::Backspace => self.edit(rt, Prompt::backspace),
+            Key::Delete => self.edit(rt, |p| p.cursor.del_char()),
+            Key::Ctrl('n') => self.edit(rt, |p| p.add_char('\n')),
+            Key::Ctrl('h') => self.edit(rt, |p| p.cursor.del_line()),
             Key::Esc => {
                 self.prompt.esc(rt);
             }
             Key::Up => match self.prompt.do_cursor(rt, Cursor::up) {
-                false => self.prompt.do_print(rt, Prompt::up),
+                false => self.edit(rt, Prompt::up),
                 _ => {}
             },
             Key::Down => match self.prompt.do_cursor(rt, Cursor::down) {
-                false => self.prompt.do_print(rt, Prompt::down),
+                false => self.edit(rt, Prompt::down),
                 _ => {}
             },
-            Key::End => self.prompt.do_cursor(rt, Cursor::to_line_end),
+            Key::End => self.edit(rt, |p| {
+                if !p.accept_suggestion(false) {
+                    p.cursor.to_line_end()
+                }
+            }),
             Key::Right => {
                 if !self.prompt.do_cursor(rt, Cursor::right) {
-                    let v = self
-                        .history
-                        .complete(&self.prompt.cursor.s, &ru_history::here(), 16);
-                    self.prompt.do_print(rt, move |p| p.set_guesses(v));
+                    self.edit(rt, |p| {
+                        p.accept_suggestion(false);
+                    });
                 }
             }
+            Key::Alt('f') => self.edit(rt, |p| {
+                p.accept_suggestion(true);
+            }),
+            Key::Alt('m') => {
+                self.prompt.do_print(rt, Prompt::jump_match);
+            }
             Key::Left => {
                 self.prompt.do_cursor(rt, Cursor::left);
             }
@@ -0,0 +1,76 @@
+//! Signals sent to the shell itself.
+//!
+//! Each foreground job runs in its own process group, given the terminal (see job.rs),
+//! so Ctrl-C, Ctrl-Z and Ctrl-\ from the terminal go to it and not the shell.
+//! Signals still reaching the shell, such as Ctrl-C during a builtin, or SIGWINCH,
+//! go through handlers rather than being ignored, so programs started still get
+//! the default actions. Ctrl-C also marks the line interrupted, so nothing more of
+//! it runs.
+use crate::events;
+use err_tools::*;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// Write end of the pipe the handler wakes the signal thread with
+static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+/// Marks that Ctrl-C was pressed, so loops and lines stop
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+pub fn clear() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Fails if Ctrl-C has been pressed since the line started
+pub fn check() -> anyhow::Result<()> {
+    match interrupted() {
+        true => e_str("Interrupted"),
+        false => Ok(()),
+    }
+}
+
+extern "C" fn on_signal(sig: libc::c_int) {
+    let fd = PIPE.load(Ordering::SeqCst);
+    if fd >= 0 {
+        let b = sig as u8;
+        unsafe { libc::write(fd, &b as *const u8 as *const libc::c_void, 1) };
+    }
+}
+
+fn handle(sig: libc::c_int) {
+    unsafe {
+        let mut sa: libc::sigaction = std::mem::zeroed();
+        sa.sa_sigaction = on_signal as *const () as libc::sighandler_t;
+        sa.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut sa.sa_mask);
+        libc::sigaction(sig, &sa, std::ptr::null_mut());
+    }
+}
+
+/// Sets up the handlers, and job control, for an interactive shell
+pub fn install() {
+    crate::job::enable();
+    ctrlc::set_handler(interrupt).ok();
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return;
+    }
+    PIPE.store(fds[1], Ordering::SeqCst);
+    std::thread::spawn(move || {
+        let mut b = 0u8;
+        while unsafe { libc::read(fds[0], &mut b as *mut u8 as *mut libc::c_void, 1) } == 1 {
+            if b as libc::c_int == libc::SIGWINCH {
+                events::resize();
+            }
+        }
+    });
+    for sig in [libc::SIGTSTP, libc::SIGQUIT, libc::SIGWINCH] {
+        handle(sig);
+    }
+}
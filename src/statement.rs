@@ -1,6 +1,9 @@
 use crate::args::Args;
+use crate::computed;
 use crate::exec::Exec;
-use crate::expr::Expr;
+use crate::expr::{exit_code, Expr};
+use crate::job;
+use crate::signals;
 use crate::store::Store;
 use err_tools::*;
 
@@ -33,6 +36,7 @@ impl Statement {
                 if ag.len() < names.len() {
                     return e_str("Not enough results for var names");
                 }
+                writable(names)?;
                 for (n, k) in names.iter().enumerate() {
                     s.let_set(k.to_string(), ag[n].clone())
                 }
@@ -43,6 +47,7 @@ impl Statement {
                 if ag.len() < names.len() {
                     return e_str("Not enough results for var names");
                 }
+                writable(names)?;
                 for (n, k) in names.iter().enumerate() {
                     s.set(k.to_string(), ag[n].clone())
                 }
@@ -53,6 +58,7 @@ impl Statement {
                 if ag.len() < names.len() {
                     return e_str("Not enough results for var names");
                 }
+                writable(names)?;
                 for (n, k) in names.iter().enumerate() {
                     s.push_set(k.to_string(), ag[n].clone())?;
                 }
@@ -63,6 +69,7 @@ impl Statement {
                 if ag.len() < names.len() {
                     return e_str("Not enough results for var names");
                 }
+                writable(names)?;
                 for (n, k) in names.into_iter().enumerate() {
                     std::env::set_var(k.to_string(), ag[n].to_string());
                     //                    s.set(k.to_string(), Data::Str(ag[n].clone()))
@@ -87,6 +94,7 @@ impl Statement {
                             ch.set(k.to_string(), v)
                         }
 
+                        signals::check()?;
                         run_block(&block, &mut ch)?;
                     }
                     Ok(())
@@ -102,6 +110,7 @@ impl Statement {
             },
             Statement::Disown(e) => {
                 let id = e.disown()?;
+                computed::add_job(&[id]);
                 println!("PID = {}", id);
                 Ok(true)
             }
@@ -140,13 +149,28 @@ impl Statement {
             Statement::Builtin("proglist", args) => {
                 let ag = args.run_s_vec(s, 1)?;
                 for a in ag {
-                    let matches = crate::tab_complete::prog_matches(&a);
+                    let matches = crate::tab_complete::prog_matches(&a, s);
                     for m in matches {
                         println!("--{}", m);
                     }
                 }
                 Ok(true)
             }
+            Statement::Builtin("history", args) => {
+                crate::history::builtin(&args.run_s_vec(s, 0)?, s)
+            }
+            Statement::Builtin(b @ ("alias" | "unalias"), args) => {
+                crate::alias::builtin(b, &args.run_s_vec(s, 0)?, s)
+            }
+            Statement::Builtin("fg", args) => {
+                let pids = match args.run_s_vec(s, 1)?.first() {
+                    Some(p) => computed::job_of(p.parse()?),
+                    None => computed::last_job().e_str("No jobs")?,
+                };
+                let st = job::resume(&pids)?;
+                computed::set_status(exit_code(&st));
+                Ok(st.success())
+            }
             Statement::Builtin("scope_depth", _) => {
                 println!("Scope depth = {}", s.scope_depth());
                 Ok(true)
@@ -171,8 +195,17 @@ impl Statement {
     }
 }
 
+/// Fails if any of names is a computed var, as they can't be set
+fn writable(names: &[String]) -> anyhow::Result<()> {
+    match names.iter().find(|n| computed::is_computed(n)) {
+        Some(n) => e_string(format!("{} is read only", n)),
+        None => Ok(()),
+    }
+}
+
 pub fn run_block(block: &[Statement], store: &mut Store) -> anyhow::Result<bool> {
     for st in block {
+        signals::check()?;
         match st.run(store) {
             Ok(_) => {}
             Err(e) => {
@@ -1,6 +1,5 @@
+use crate::computed;
 use crate::data::*;
-use crate::parser;
-use bogobble::traits::*;
 use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::io::Read;
@@ -107,6 +106,9 @@ impl Store {
         })))
     }
     pub fn get(&self, k: &str) -> Option<Data> {
+        if let Some(d) = computed::get(k) {
+            return Some(d);
+        }
         self.0.borrow().get(k)
     }
 
@@ -173,10 +175,11 @@ impl Store {
         }
     }
     pub fn source_path<P: AsRef<Path>>(&mut self, p: P) -> anyhow::Result<()> {
+        let name = p.as_ref().display().to_string();
         let mut f = std::fs::File::open(p)?;
         let mut buf = String::new();
         f.read_to_string(&mut buf)?;
-        let p = parser::Lines.parse_s(&buf).map_err(|e| e.strung())?;
+        let p = crate::diagnose::parse_lines(&buf, &name)?;
         for v in p {
             v.run(self).ok();
         }
@@ -196,30 +199,6 @@ impl Store {
     }
 }
 
-//consider where to put this func
-pub fn alias(s: &str, store: &Store) -> Option<String> {
-    let mut res = None;
-    store.do_with("RU_ALIAS", |d| {
-        if let Data::Map(m) = d {
-            for (k, v) in m {
-                if s.starts_with(k) {
-                    let mut vs = v.to_string();
-                    let push = &s[k.len()..];
-                    match push.chars().next() {
-                        Some(' ') | Some('\t') | None => {
-                            vs.push_str(&s[k.len()..]);
-                            res = Some(vs);
-                            return;
-                        }
-                        _ => {}
-                    }
-                }
-            }
-        }
-    });
-    res
-}
-
 /*impl Store {
     pub fn new() -> Self {
         Self {
@@ -79,6 +79,37 @@ pub fn str_agree(a: &str, b: &str) -> usize {
     a.len()
 }
 
+/// The end of the first word in s, after any leading whitespace
+pub fn next_word_end(s: &str) -> usize {
+    let start = s.len() - s.trim_start().len();
+    match s[start..].find(char::is_whitespace) {
+        Some(n) => start + n,
+        None => s.len(),
+    }
+}
+
+/// Edits needed to turn a into b, swapping two neighbouring chars counts as one
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d: Vec<Vec<usize>> = (0..=a.len())
+        .map(|i| (0..=b.len()).map(|j| if i == 0 { j } else { i }).collect())
+        .collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut v = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                v = v.min(d[i - 2][j - 2] + 1);
+            }
+            d[i][j] = v;
+        }
+    }
+    d[a.len()][b.len()]
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -94,6 +125,22 @@ mod test {
         assert_eq!("hello\nworld".char_right_n_match(2, 10, '\n'), Some(5));
     }
 
+    #[test]
+    fn word_end() {
+        assert_eq!(next_word_end(" checkout main"), 9);
+        assert_eq!(next_word_end("main"), 4);
+        assert_eq!(next_word_end(""), 0);
+    }
+
+    #[test]
+    fn edits() {
+        assert_eq!(edit_distance("sl", "ls"), 1);
+        assert_eq!(edit_distance("gti", "git"), 1);
+        assert_eq!(edit_distance("carg", "cargo"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+
     #[test]
     fn previous_match() {
         assert_eq!("hello world".prev_match(' ', 8), Some(5));
@@ -1,14 +1,14 @@
+use crate::comp_spec;
 use crate::data::Data;
+use crate::fish;
+use crate::fuzzy::{self, fuzzy_match};
+use crate::menu::Candidate;
 use crate::parser;
+use crate::path_index;
 use crate::store::Store;
 use crate::str_util;
-use bogobble::traits::*;
-use chrono::*;
-use ru_history::HistoryStore;
-use std::collections::BTreeSet;
-use std::io::ErrorKind;
-use std::path::{Path, PathBuf};
-use std::time::SystemTime;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
 
 fn dir_slash(p: &Path, td: Option<&String>) -> String {
     let mut s = p
@@ -40,84 +40,117 @@ pub fn all_strs_agree<I: Iterator<Item = S>, S: AsRef<str>>(
     Some(res[..max].to_string())
 }
 
-pub fn tab_complete_args(s: &str, c: &str, store: &mut Store) -> anyhow::Result<Vec<String>> {
-    // println!("Tab complete args \n\r\n");
-
+/// Completes from the RU_COMPLETE spec for words[0].
+/// Returns None if there is no spec for the command.
+pub fn tab_complete_args(
+    s: &str,
+    words: &[String],
+    store: &mut Store,
+) -> anyhow::Result<Option<Vec<Candidate>>> {
+    let c = match words.first() {
+        Some(c) => c,
+        None => return Ok(None),
+    };
     let mut op = None;
     store.do_with("RU_COMPLETE", |m| {
         if let Data::Map(mp) = m {
-            op = mp.get(c).map(|c| c.clone())
+            op = mp.get(c).cloned()
         }
     });
 
-    let mut res = Vec::new();
-    if let Some(a_str) = op {
-        let args = parser::ArgsP
-            .parse_s(&a_str.to_string())
-            .map_err(|e| e.strung())?;
-        let a_done = args.run_s_vec(store, 2)?;
+    let spec = match op.or_else(|| fish::spec_for(c, store)) {
+        Some(sp) => sp,
+        None => return Ok(None),
+    };
+    let mut res = comp_spec::complete(&spec, words, s, store)?;
+    res.retain(|c| fuzzy_match(s, &c.text).is_some());
+    Ok(Some(res))
+}
 
-        for a in &a_done {
-            if a.starts_with(s) {
-                res.push(a.to_string());
-            }
+/// A short one line version of a value, to describe a completion
+fn short_desc(s: &str) -> String {
+    let s = s.replace('\n', " ");
+    match s.char_indices().nth(30) {
+        Some((n, _)) => format!("{}..", &s[..n]),
+        None => s,
+    }
+}
+
+/// Names of variables in every scope and the environment, with their values.
+/// pre is put back in front, for "$" and "${"
+pub fn tab_complete_var(pre: &str, s: &str, store: &Store) -> Vec<Candidate> {
+    let mut mp: BTreeMap<String, String> = std::env::vars().collect();
+    store.for_each(|k, v, _| {
+        mp.insert(k.to_string(), v.to_string());
+    });
+    mp.into_iter()
+        .filter(|(k, _)| fuzzy_match(s, k).is_some())
+        .map(|(k, v)| Candidate::with_desc(format!("{}{}", pre, k), short_desc(&v)))
+        .collect()
+}
+
+/// Anything that can start a statement: keywords, builtins, aliases and programs
+pub fn tab_complete_command(s: &str, store: &Store) -> Vec<Candidate> {
+    if s.starts_with("./") || s.starts_with('/') {
+        return tab_complete_prog(s, store)
+            .into_iter()
+            .map(Candidate::from)
+            .collect();
+    }
+    let mut res = Vec::new();
+    let mut add = |k: &str, desc: &str| {
+        if fuzzy_match(s, k).is_some() {
+            res.push(Candidate::with_desc(k, desc));
         }
+    };
+    for k in parser::KEYWORDS {
+        add(k, "keyword");
+    }
+    for k in parser::ASSIGNERS {
+        add(k, "assigner");
     }
-    Ok(res)
+    for k in parser::BUILTINS {
+        add(k, "builtin");
+    }
+    store.do_with("RU_ALIAS", |d| {
+        if let Data::Map(m) = d {
+            for (k, v) in m {
+                add(k, &format!("alias {}", short_desc(&v.to_string())));
+            }
+        }
+    });
+    res.extend(tab_complete_prog(s, store).into_iter().map(Candidate::from));
+    res
 }
 
-pub fn tab_complete_prog(s: &str) -> Vec<String> {
-    if s.starts_with("./") {
-        return tab_complete_path(&s[2..])
+pub fn tab_complete_prog(s: &str, store: &Store) -> Vec<String> {
+    let runnable = |p: &Path| p.is_dir() || path_index::is_executable(p);
+    if let Some(rest) = s.strip_prefix("./") {
+        return tab_complete_path_with(rest, runnable)
             .into_iter()
             .map(|s| format!("./{}", s))
             .collect();
     }
-    if s.starts_with("/") {
-        return tab_complete_path(s);
+    if s.starts_with('/') {
+        return tab_complete_path_with(s, runnable);
     }
 
-    let list: Vec<String> = prog_matches(s).into_iter().collect();
-    list
+    fuzzy::rank(s, path_index::programs(store))
 }
 
-pub fn prog_matches(s: &str) -> BTreeSet<String> {
-    let mut res = BTreeSet::new();
-    let v = std::env::var("PATH").unwrap_or("".to_string());
-    for a in v.split(":") {
-        folder_prog_matches(a, s, &mut res);
-    }
+/// Programs on the PATH starting with s
+pub fn prog_matches(s: &str, store: &Store) -> BTreeSet<String> {
+    let mut res = path_index::programs(store);
+    res.retain(|p| p.starts_with(s));
     res
 }
 
-fn folder_prog_matches<P: AsRef<Path>>(folder: P, s: &str, v: &mut BTreeSet<String>) {
-    let entries = match std::fs::read_dir(folder.as_ref()) {
-        Ok(dirs) => dirs,
-        _ => return,
-    };
-    for e in entries {
-        match e {
-            Ok(entry) => {
-                let ft = match entry.file_type() {
-                    Ok(f) => f,
-                    _ => continue,
-                };
-                if ft.is_dir() {
-                    //                folder_prog_matches(entry.path(), s, v);
-                } else {
-                    let fname = entry.file_name();
-                    let fstr = fname.to_string_lossy();
-                    if fstr.starts_with(s) {
-                        v.insert(fstr.to_string());
-                    }
-                }
-            }
-            _ => continue, // If an issue, carry on
-        }
-    }
+pub fn tab_complete_path(src: &str) -> Vec<String> {
+    tab_complete_path_with(src, |_| true)
 }
 
-pub fn tab_complete_path(src: &str) -> Vec<String> {
+/// Completes paths, keeping only those passing the filter
+pub fn tab_complete_path_with<F: Fn(&Path) -> bool>(src: &str, filter: F) -> Vec<String> {
     let (s, hd) = match src.starts_with("~") {
         true => {
             let hd = std::env::var("HOME").unwrap_or("".to_string());
@@ -129,6 +162,7 @@ pub fn tab_complete_path(src: &str) -> Vec<String> {
     let g = glob::glob(&sg)
         .map(|m| {
             m.filter_map(|a| a.ok())
+                .filter(|d| filter(d))
                 .map(|d| dir_slash(&d, hd.as_ref()))
                 .collect()
         })
@@ -138,7 +172,13 @@ pub fn tab_complete_path(src: &str) -> Vec<String> {
             return vec![s];
         }
     }
-    g
+    let mut res = g;
+    for f in fuzzy_path_matches(&s, hd.as_ref(), &filter) {
+        if !res.contains(&f) {
+            res.push(f);
+        }
+    }
+    res
     /*match g.len() {
         0 => return v,
         1 => {
@@ -155,54 +195,39 @@ pub fn tab_complete_path(src: &str) -> Vec<String> {
     }*/
 }
 
-fn history_path() -> PathBuf {
-    let mut tdir = PathBuf::from(std::env::var("HOME").unwrap_or(String::new()));
-    tdir.push(".config/rushell/history");
-    tdir
-}
-
-fn on_year_month(p: &Path, y: i32, m: u32) -> PathBuf {
-    let dt_s = format!("history_{}_{}.fd", y, m);
-    p.join(&dt_s)
-}
-
-fn year_month(t: SystemTime) -> (i32, u32) {
-    let dt: DateTime<offset::Local> = DateTime::from(t);
-    (dt.year(), dt.month())
-}
-
-//Currently just append to file and hope for the best.
-pub fn load_history(months: u32, hist: &mut HistoryStore) -> anyhow::Result<()> {
-    let (y, m) = year_month(SystemTime::now());
-    let path = history_path();
-
-    for n in 1..=months {
-        let sub = months - n;
-        let y2 = y - (sub as i32 / 12);
-        let m2 = ((m + 11 - sub as u32) % 12) + 1;
-        let p2 = on_year_month(&path, y2, m2);
-
-        match std::fs::read_to_string(p2) {
-            Ok(s) => ru_history::parse::parse_onto(hist, &s)?,
-            Err(e) => match e.kind() {
-                ErrorKind::NotFound => continue,
-                _ => return Err(e.into()),
-            },
+/// Entries in the folder of s, whose names contain the rest of s in order.
+/// Best matches first
+fn fuzzy_path_matches<F: Fn(&Path) -> bool>(
+    s: &str,
+    hd: Option<&String>,
+    filter: F,
+) -> Vec<String> {
+    let s = s.replace("\\ ", " ");
+    let (dir, file) = match s.rfind('/') {
+        Some(n) => (&s[..=n], &s[n + 1..]),
+        None => ("", &s[..]),
+    };
+    if file.is_empty() {
+        return Vec::new();
+    }
+    let rd = match std::fs::read_dir(if dir.is_empty() { "." } else { dir }) {
+        Ok(rd) => rd,
+        Err(_) => return Vec::new(),
+    };
+    let mut v: Vec<(i64, String)> = Vec::new();
+    for e in rd.filter_map(|e| e.ok()) {
+        let name = e.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') && !file.starts_with('.') {
+            continue;
+        }
+        let p = Path::new(dir).join(&name);
+        if !filter(&p) {
+            continue;
+        }
+        if let Some(m) = fuzzy::fuzzy_match(file, &name) {
+            v.push((m.score, dir_slash(&p, hd)));
         }
     }
-    Ok(())
-}
-
-pub fn save_history(hs: &mut HistoryStore) -> anyhow::Result<()> {
-    let (y, m) = year_month(SystemTime::now());
-    let hpath = history_path();
-    std::fs::create_dir_all(hpath).ok();
-    let path = on_year_month(&history_path(), y, m);
-    let mut f = std::fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .write(true)
-        .open(path)?;
-    hs.write_to(&mut f, false)?;
-    Ok(())
+    v.sort_by_key(|(sc, _)| std::cmp::Reverse(*sc));
+    v.into_iter().map(|(_, s)| s).collect()
 }
@@ -0,0 +1,213 @@
+//! Wall clock and CPU time for commands.
+//!
+//! Programs are waited on with "wait4", which gives the CPU they used, and that is
+//! added to a running total, so the time for anything is the change in the total.
+use crate::computed::{self, fmt_duration};
+use crate::job::Job;
+use std::fmt;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, ExitStatus};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// CPU used by programs waited on so far, in microseconds
+static USER: AtomicU64 = AtomicU64::new(0);
+static SYS: AtomicU64 = AtomicU64::new(0);
+
+fn micros(t: libc::timeval) -> u64 {
+    (t.tv_sec as u64) * 1_000_000 + t.tv_usec as u64
+}
+
+/// Waits for ch to finish, adding its CPU time to the totals
+pub fn wait(ch: &mut Child) -> std::io::Result<ExitStatus> {
+    wait_pids(&[ch.id()])
+}
+
+/// Waits for every program in job, so all the CPU of a pipe is counted,
+/// giving the status of ch, the last of them
+pub fn wait_job(job: &Job, ch: &mut Child) -> std::io::Result<ExitStatus> {
+    match job.pids().contains(&ch.id()) {
+        true => wait_pids(job.pids()),
+        false => wait(ch),
+    }
+}
+
+/// Waits for the program with pid to finish or stop, adding its CPU time
+fn wait4(pid: u32) -> std::io::Result<libc::c_int> {
+    let mut status = 0;
+    let mut ru: libc::rusage = unsafe { std::mem::zeroed() };
+    let pid = pid as libc::pid_t;
+    while unsafe { libc::wait4(pid, &mut status, libc::WUNTRACED, &mut ru) } == -1 {
+        let e = std::io::Error::last_os_error();
+        if e.kind() != std::io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    USER.fetch_add(micros(ru.ru_utime), Ordering::SeqCst);
+    SYS.fetch_add(micros(ru.ru_stime), Ordering::SeqCst);
+    Ok(status)
+}
+
+/// Waits for the programs of a job to finish, or be stopped by Ctrl-Z, giving the
+/// status of the last. Those stopped join the jobs, to carry on with "fg"
+pub fn wait_pids(pids: &[u32]) -> std::io::Result<ExitStatus> {
+    let (last, rest) = pids.split_last().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "No programs to wait on")
+    })?;
+    let mut stopped = Vec::new();
+    for &pid in rest {
+        if let Ok(st) = wait4(pid) {
+            if libc::WIFSTOPPED(st) {
+                stopped.push(pid);
+            }
+        }
+    }
+    let status = wait4(*last)?;
+    if libc::WIFSTOPPED(status) {
+        stopped.push(*last);
+    }
+    if let Some(first) = stopped.first() {
+        computed::add_job(&stopped);
+        eprintln!("\nStopped : PID = {}", first);
+    }
+    Ok(ExitStatus::from_raw(status))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Times {
+    pub wall: Duration,
+    pub user: Duration,
+    pub sys: Duration,
+}
+
+impl Times {
+    pub fn cpu(&self) -> Duration {
+        self.user + self.sys
+    }
+}
+
+impl fmt::Display for Times {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} wall, {} user, {} sys",
+            fmt_duration(self.wall),
+            fmt_duration(self.user),
+            fmt_duration(self.sys)
+        )
+    }
+}
+
+pub struct Timer {
+    start: Instant,
+    user: u64,
+    sys: u64,
+}
+
+impl Timer {
+    pub fn start() -> Self {
+        Timer {
+            start: Instant::now(),
+            user: USER.load(Ordering::SeqCst),
+            sys: SYS.load(Ordering::SeqCst),
+        }
+    }
+
+    pub fn stop(&self) -> Times {
+        Times {
+            wall: self.start.elapsed(),
+            user: Duration::from_micros(USER.load(Ordering::SeqCst) - self.user),
+            sys: Duration::from_micros(SYS.load(Ordering::SeqCst) - self.sys),
+        }
+    }
+}
+
+/// Tells the terminal a slow command is done, if it took longer than
+/// "RU_NOTIFY_AFTER" seconds. "RU_NOTIFY" picks how: "bell" (the default),
+/// "osc777" or "osc9" for a desktop notification
+pub fn notify(cmd: &str, t: &Times, store: &crate::store::Store) {
+    let after: f64 = match store
+        .get("RU_NOTIFY_AFTER")
+        .and_then(|d| d.to_string().trim().parse().ok())
+    {
+        Some(a) => a,
+        None => return,
+    };
+    if t.wall.as_secs_f64() < after {
+        return;
+    }
+    let first = cmd.lines().next().unwrap_or("");
+    let msg = format!("{} finished in {}", first, fmt_duration(t.wall));
+    let how = store.get("RU_NOTIFY").map(|d| d.to_string());
+    match how.as_deref() {
+        Some("osc777") => print!("\x1b]777;notify;ru_shell;{}\x07", msg),
+        Some("osc9") => print!("\x1b]9;{}\x07", msg),
+        _ => print!("\x07"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn waits_count_cpu() {
+        let t = Timer::start();
+        let mut ch = std::process::Command::new("sh")
+            .args([
+                "-c",
+                "i=0; while [ $i -lt 20000 ]; do i=$((i+1)); done; exit 3",
+            ])
+            .spawn()
+            .unwrap();
+        let st = wait(&mut ch).unwrap();
+        assert_eq!(st.code(), Some(3));
+        let times = t.stop();
+        assert!(times.cpu() > Duration::ZERO);
+
+        //The first of a pipe does the work, and is reaped by wait_job
+        let t = Timer::start();
+        let mut job = Job::none();
+        #[allow(clippy::zombie_processes)]
+        let mut first = std::process::Command::new("sh")
+            .args(["-c", "i=0; while [ $i -lt 100000 ]; do i=$((i+1)); done"])
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        job.started(first.id());
+        let mut last = std::process::Command::new("cat")
+            .stdin(first.stdout.take().unwrap())
+            .spawn()
+            .unwrap();
+        job.started(last.id());
+        assert!(wait_job(&job, &mut last).unwrap().success());
+        assert!(t.stop().cpu() > Duration::from_millis(20));
+    }
+
+    #[test]
+    fn stopped_pipe_is_one_job() {
+        #[allow(clippy::zombie_processes)]
+        let sleep = || {
+            std::process::Command::new("sleep")
+                .arg("0.2")
+                .spawn()
+                .unwrap()
+        };
+        let pids = [sleep().id(), sleep().id()];
+        for p in pids {
+            unsafe { libc::kill(p as libc::pid_t, libc::SIGSTOP) };
+        }
+        let st = wait_pids(&pids).unwrap();
+        assert_eq!(st.stopped_signal(), Some(libc::SIGSTOP));
+        assert_eq!(computed::job_of(pids[1]), pids);
+
+        //Stopped again after "fg", it is still one job
+        let jobs = || computed::get("RU_JOBS").unwrap().to_string();
+        let n = jobs();
+        computed::add_job(&pids);
+        assert_eq!(jobs(), n);
+
+        assert!(crate::job::resume(&pids).unwrap().success());
+        //Both were reaped, so neither is a job now
+        assert_eq!(computed::job_of(pids[0]), [pids[0]]);
+    }
+}
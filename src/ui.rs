@@ -1,25 +1,72 @@
-use crate::RT;
+use std::fmt::Write;
 use termion::*;
+use unicode_width::UnicodeWidthChar;
 
-pub fn line_count(s: &str, w: usize) -> usize {
-    let mut res = 0;
-    let mut cw = 0;
+/// The columns s takes up, not counting escapes
+pub fn text_width(s: &str) -> usize {
+    console::measure_text_width(s)
+}
+
+/// The row and column the cursor ends at after printing s from the start of a
+/// line at width w. Wide chars that don't fit wrap early, as in the terminal.
+/// A column of w means the line is full, and the next char will wrap
+pub fn end_pos(s: &str, w: usize) -> (usize, usize) {
+    let s = console::strip_ansi_codes(s);
+    let (mut row, mut col) = (0, 0);
     for c in s.chars() {
         match c {
             '\n' => {
-                res += 1;
-                cw = 0;
+                row += 1;
+                col = 0;
             }
+            '\r' => col = 0,
+            '\t' => col = ((col / 8 + 1) * 8).min(w),
             _ => {
-                if cw >= w {
-                    res += 1;
-                    cw = 0;
-                } else {
-                    cw += 1;
+                let cw = c.width().unwrap_or(0);
+                if cw == 0 {
+                    continue;
+                }
+                if col + cw > w {
+                    row += 1;
+                    col = 0;
                 }
+                col += cw;
             }
         }
     }
+    (row, col)
+}
+
+/// Styles the char at byte idx of the text under the escapes in s with on,
+/// then puts back the style that was in use there
+pub fn mark_char(s: &str, idx: usize, on: &str) -> String {
+    let mut res = String::new();
+    let mut active = String::new();
+    let mut n = 0;
+    let mut it = s.chars();
+    while let Some(c) = it.next() {
+        if c == '\x1b' {
+            let mut esc = String::from(c);
+            for e in it.by_ref() {
+                esc.push(e);
+                if e != '[' && ('@'..='~').contains(&e) {
+                    break;
+                }
+            }
+            if esc == "\x1b[m" || esc.starts_with("\x1b[0;") || esc == "\x1b[0m" {
+                active.clear();
+            }
+            active.push_str(&esc);
+            res.push_str(&esc);
+            continue;
+        }
+        if n == idx {
+            write!(res, "{}{}{}{}", on, c, style::Reset, active).ok();
+        } else {
+            res.push(c);
+        }
+        n += c.len_utf8();
+    }
     res
 }
 
@@ -65,26 +112,6 @@ pub fn print(s: &str) {
     }
 }
 
-pub fn unprint(s: &str, _rt: &mut RT, del: bool) {
-    let s = console::strip_ansi_codes(s);
-    let (t_width, _) = termion::terminal_size().unwrap_or((50, 50));
-    let lcount = crate::ui::line_count(&s, t_width as usize);
-    if lcount > 0 {
-        print!("{}", cursor::Up(lcount as u16));
-    }
-    print!("\r");
-    if del {
-        print!("{}", clear::AfterCursor,);
-    }
-}
-
-pub fn char_as_int(c: char) -> Option<usize> {
-    match c {
-        n if n >= '0' && n <= '9' => Some(n as usize - 48),
-        _ => None,
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -95,4 +122,28 @@ mod tests {
         assert_eq!(del_n("hello", 3), "he");
         assert_eq!(del_n("我不是中国人", 2), "我不是中");
     }
+
+    #[test]
+    fn escapes_take_no_width() {
+        let s = "\x1b[0;31mabc\x1b[m\ndef";
+        assert_eq!(end_pos(s, 3), (1, 3));
+        assert_eq!(end_pos(s, 2), (3, 1));
+        assert_eq!(text_width("\x1b[1mab\x1b[m"), 2);
+    }
+
+    #[test]
+    fn wide_chars_wrap_early() {
+        assert_eq!(end_pos("你们", 4), (0, 4));
+        assert_eq!(end_pos("你们", 3), (1, 2));
+        assert_eq!(end_pos("a你们", 4), (1, 2));
+    }
+
+    #[test]
+    fn mark_restores_style() {
+        let s = "\x1b[31mab\x1b[0;1m(c)";
+        assert_eq!(
+            mark_char(s, 2, "\x1b[7m"),
+            "\x1b[31mab\x1b[0;1m\x1b[7m(\x1b[m\x1b[0;1mc)"
+        );
+    }
 }
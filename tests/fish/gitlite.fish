@@ -0,0 +1,17 @@
+# Completions for gitlite, a small git look-alike used by the fish import tests
+complete -c gitlite -f
+complete -c gitlite -s C -r -d 'Run as if started in <path>'
+complete -c gitlite -l version -d 'Print the version'
+complete -c gitlite -l color -x -a 'always never auto' -d 'When to use colour'
+
+complete -c gitlite -n '__fish_use_subcommand' -a checkout -d 'Switch branches or restore files'
+complete -c gitlite -n '__fish_use_subcommand' -a add -d 'Add file contents to the index'
+complete -c gitlite -n '__fish_use_subcommand' -a 'status log' -d 'Show the state of things'
+
+complete -c gitlite -n '__fish_seen_subcommand_from checkout switch' -s b -d 'Create a new branch'
+complete -c gitlite -n '__fish_seen_subcommand_from checkout switch' -a '(gitlite branch --list)'
+complete -c gitlite -n '__fish_seen_subcommand_from add' -F
+complete -c gitlite -n '__fish_seen_subcommand_from add' -s p -l patch -d 'Interactively choose hunks'
+
+# Conditions that need fish functions cannot be followed, so are skipped
+complete -c gitlite -n '__fish_gitlite_needs_remote' -a '(__fish_gitlite_remotes)'
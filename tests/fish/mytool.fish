@@ -0,0 +1,6 @@
+complete --command mytool --short-option o --long-option output \
+    --require-parameter --description "Output file"
+complete -c mytool -l mode -x -a "fast slow" -d "How hard to try"
+complete -c mytool -o quiet -d "Say nothing"
+complete -c mytool --long-option=level --exclusive --arguments='1 2 3'
+complete -c mytool -a "build test" -d "Run \"it\""